@@ -11,7 +11,18 @@ use std::cell::RefCell;
 struct WriteEntity {
     uri: String,
     length: i32,
-    fps: i32,
+
+    // updates the frame rate of the project, which the output is rendered at
+    #[serde(default)]
+    fps: Option<i32>,
+
+    // "avi" or "png" (sequence of files in the directory of uri)
+    #[serde(default = "format_default")]
+    format: String,
+}
+
+fn format_default() -> String {
+    "avi".to_string()
 }
 
 #[derive(Serialize)]
@@ -51,11 +62,34 @@ fn req(app: Rc<RefCell<Madder>>, msg: ws::Message, socket: &ws::Sender) -> Resul
     } else if req.path == "/write" {
         // I know this is a bad way to block main thread, but ...
         let write_entity = serde_json::from_value::<WriteEntity>(req.entity).unwrap();
-        app.borrow_mut().start_render(&write_entity.uri, write_entity.length * write_entity.fps, write_entity.fps);
+        let mut result = Ok(());
+        if let Some(fps) = write_entity.fps {
+            let mut entity = serde_json::Map::new();
+            entity.insert("fps".to_string(), serde_json::Value::from(fps));
+            result = app.borrow_mut().request(Method::Update, "/project", serde_json::Value::Object(entity)).map(|_| ());
+        }
 
-        let response = Response {
-            status: 200,
-            body: "{}".to_string(),
+        let result = result.and_then(|()| {
+            let frames = write_entity.length * app.borrow().project().fps;
+            match write_entity.format.as_str() {
+                "png" => app.borrow().save_frames(&write_entity.uri, frames),
+                _ => app.borrow_mut().start_render(&write_entity.uri, frames),
+            }
+        });
+
+        let response = match result {
+            Ok(()) => {
+                Response {
+                    status: 200,
+                    body: "{}".to_string(),
+                }
+            },
+            Err(err) => {
+                Response {
+                    status: 500,
+                    body: err,
+                }
+            },
         };
         socket.send(response.to_message())
     } else {
@@ -42,7 +42,10 @@ fn main() {
         let yaml = serde_yaml::from_str::<AppYaml>(&contents).unwrap();
 
         let mut app = App::new();
-        app.madder.from_yaml(yaml.project).unwrap();
+        if let Err(err) = app.madder.from_yaml(yaml.project) {
+            eprintln!("Failed to load {}: {}", args[1], err);
+            ::std::process::exit(1);
+        }
 
         for op in yaml.operations {
             app.madder.request(op.method, &op.path, op.entity).unwrap();
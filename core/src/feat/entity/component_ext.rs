@@ -1,7 +1,7 @@
 extern crate serde_json;
 extern crate gdk_pixbuf;
 extern crate gstreamer as gst;
-use spec::{Component, HaveComponent};
+use spec::{Component, HaveComponent, ProxyStatus};
 use feat::*;
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -11,6 +11,8 @@ pub enum ComponentExt {
     Image(ImageComponent),
     Sound(SoundComponent),
     Text(TextComponent),
+    Composition(CompositionComponent),
+    Adjustment(AdjustmentComponent),
 }
 
 impl ComponentExt {
@@ -23,6 +25,8 @@ impl ComponentExt {
             "Image" => Some(Image(ImageComponent::new(json))),
             "Sound" => Some(Sound(SoundComponent::new(json))),
             "Text" => Some(Text(TextComponent::new(json))),
+            "Composition" => Some(Composition(CompositionComponent::new(json))),
+            "Adjustment" => Some(Adjustment(AdjustmentComponent::new(json))),
             _ => unreachable!(),
         }
     }
@@ -37,6 +41,8 @@ impl HaveComponent for ComponentExt {
             Image(c) => c.component(),
             Sound(c) => c.component(),
             Text(c) => c.component(),
+            Composition(c) => c.component(),
+            Adjustment(c) => c.component(),
         }
     }
 
@@ -48,6 +54,8 @@ impl HaveComponent for ComponentExt {
             Image(c) => c.component_mut(),
             Sound(c) => c.component_mut(),
             Text(c) => c.component_mut(),
+            Composition(c) => c.component_mut(),
+            Adjustment(c) => c.component_mut(),
         }
     }
 
@@ -59,6 +67,99 @@ impl HaveComponent for ComponentExt {
             Image(c) => c.get_pixbuf(time),
             Sound(c) => c.get_pixbuf(time),
             Text(c) => c.get_pixbuf(time),
+            Composition(c) => c.get_pixbuf(time),
+            Adjustment(c) => c.get_pixbuf(time),
+        }
+    }
+
+    fn get_preview_pixbuf(&self, time: gst::ClockTime) -> Option<gdk_pixbuf::Pixbuf> {
+        use ComponentExt::*;
+
+        match self {
+            Video(c) => c.get_preview_pixbuf(time),
+            Image(c) => c.get_preview_pixbuf(time),
+            Sound(c) => c.get_preview_pixbuf(time),
+            Text(c) => c.get_preview_pixbuf(time),
+            Composition(c) => c.get_preview_pixbuf(time),
+            Adjustment(c) => c.get_preview_pixbuf(time),
+        }
+    }
+
+    fn proxy_status(&self) -> ProxyStatus {
+        use ComponentExt::*;
+
+        match self {
+            Video(c) => c.proxy_status(),
+            Image(c) => c.proxy_status(),
+            Sound(c) => c.proxy_status(),
+            Text(c) => c.proxy_status(),
+            Composition(c) => c.proxy_status(),
+            Adjustment(c) => c.proxy_status(),
+        }
+    }
+
+    fn generate_proxy(&mut self, cache_dir: &str) -> Result<(), String> {
+        use ComponentExt::*;
+
+        match self {
+            Video(c) => c.generate_proxy(cache_dir),
+            Image(c) => c.generate_proxy(cache_dir),
+            Sound(c) => c.generate_proxy(cache_dir),
+            Text(c) => c.generate_proxy(cache_dir),
+            Composition(c) => c.generate_proxy(cache_dir),
+            Adjustment(c) => c.generate_proxy(cache_dir),
+        }
+    }
+
+    fn source_size(&self) -> Option<(i32, i32)> {
+        use ComponentExt::*;
+
+        match self {
+            Video(c) => c.source_size(),
+            Image(c) => c.source_size(),
+            Sound(c) => c.source_size(),
+            Text(c) => c.source_size(),
+            Composition(c) => c.source_size(),
+            Adjustment(c) => c.source_size(),
+        }
+    }
+
+    fn is_adjustment(&self) -> bool {
+        use ComponentExt::*;
+
+        match self {
+            Video(c) => c.is_adjustment(),
+            Image(c) => c.is_adjustment(),
+            Sound(c) => c.is_adjustment(),
+            Text(c) => c.is_adjustment(),
+            Composition(c) => c.is_adjustment(),
+            Adjustment(c) => c.is_adjustment(),
+        }
+    }
+
+    fn composition(&self) -> Option<&str> {
+        use ComponentExt::*;
+
+        match self {
+            Video(c) => c.composition(),
+            Image(c) => c.composition(),
+            Sound(c) => c.composition(),
+            Text(c) => c.composition(),
+            Composition(c) => c.composition(),
+            Adjustment(c) => c.composition(),
+        }
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        use ComponentExt::*;
+
+        match self {
+            Video(c) => c.frame_rate(),
+            Image(c) => c.frame_rate(),
+            Sound(c) => c.frame_rate(),
+            Text(c) => c.frame_rate(),
+            Composition(c) => c.frame_rate(),
+            Adjustment(c) => c.frame_rate(),
         }
     }
 
@@ -70,6 +171,8 @@ impl HaveComponent for ComponentExt {
             Image(c) => c.get_audio_elements(),
             Sound(c) => c.get_audio_elements(),
             Text(c) => c.get_audio_elements(),
+            Composition(c) => c.get_audio_elements(),
+            Adjustment(c) => c.get_audio_elements(),
         }
     }
 
@@ -81,6 +184,8 @@ impl HaveComponent for ComponentExt {
             Image(c) => c.tick(),
             Sound(c) => c.tick(),
             Text(c) => c.tick(),
+            Composition(c) => c.tick(),
+            Adjustment(c) => c.tick(),
         }
     }
 }
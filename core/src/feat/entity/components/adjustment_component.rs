@@ -0,0 +1,30 @@
+extern crate serde_json;
+use spec::*;
+
+// no pixels of its own, its effects are applied to the composite of the layers below
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AdjustmentComponent {
+    #[serde(flatten)]
+    component: Component,
+}
+
+impl AdjustmentComponent {
+    pub fn new(json: serde_json::Value) -> AdjustmentComponent {
+        serde_json::from_value(json).unwrap()
+    }
+}
+
+impl HaveComponent for AdjustmentComponent {
+    fn component(&self) -> &Component {
+        &self.component
+    }
+
+    fn component_mut(&mut self) -> &mut Component {
+        &mut self.component
+    }
+
+    fn is_adjustment(&self) -> bool {
+        true
+    }
+}
+
@@ -0,0 +1,34 @@
+extern crate gstreamer as gst;
+extern crate gdk_pixbuf;
+extern crate serde_json;
+use spec::*;
+
+// a composition of the project placed as a clip, rendered by the presenter at the local time
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CompositionComponent {
+    #[serde(flatten)]
+    component: Component,
+
+    composition: String,
+}
+
+impl CompositionComponent {
+    pub fn new(json: serde_json::Value) -> CompositionComponent {
+        serde_json::from_value(json).unwrap()
+    }
+}
+
+impl HaveComponent for CompositionComponent {
+    fn component(&self) -> &Component {
+        &self.component
+    }
+
+    fn component_mut(&mut self) -> &mut Component {
+        &mut self.component
+    }
+
+    fn composition(&self) -> Option<&str> {
+        Some(&self.composition)
+    }
+}
+
@@ -10,3 +10,9 @@ pub use self::text_component::*;
 mod sound_component;
 pub use self::sound_component::*;
 
+
+mod composition_component;
+pub use self::composition_component::*;
+
+mod adjustment_component;
+pub use self::adjustment_component::*;
@@ -53,8 +53,22 @@ impl HaveComponent for SoundComponent {
         &mut self.component
     }
 
+    // played at the speed of the component, keeping the pitch
     fn get_audio_elements(&self) -> Vec<gst::Element> {
-        self.data.clone()
+        let speed = self.component.speed;
+        if speed == 1.0 {
+            return self.data.clone();
+        }
+
+        let pitch = gst::ElementFactory::make("pitch", None).unwrap();
+        pitch.set_property("tempo", &glib::Value::from(&(speed as f32))).unwrap();
+        let convert = gst::ElementFactory::make("audioconvert", None).unwrap();
+
+        // before the queue at the end
+        let mut elements = self.data.clone();
+        let queue = elements.pop().unwrap();
+        elements.extend(vec![pitch, convert, queue]);
+        elements
     }
 }
 
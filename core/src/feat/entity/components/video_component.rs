@@ -3,12 +3,57 @@ extern crate gstreamer_app as gsta;
 extern crate gstreamer_video as gstv;
 extern crate gdk_pixbuf;
 extern crate glib;
+extern crate serde;
 extern crate serde_json;
-use std::{thread, time};
+use std::{thread, time, fs};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use gst::prelude::*;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use spec::*;
 
+// height of proxies, the width follows the aspect ratio of the original
+const PROXY_HEIGHT: i32 = 360;
+
 #[derive(Clone, Serialize, Deserialize)]
+pub struct Proxy {
+    path: String,
+
+    // size of the original, where the geometry of proxy frames is scaled up to
+    size: (i32, i32),
+}
+
+// shared with the thread generating the proxy
+enum ProxyState {
+    NotGenerated,
+
+    // saved in the project, opened on load
+    Saved(Proxy),
+    Generating(f64),
+    Ready(Proxy, gst::Pipeline),
+    Failed(String),
+}
+
+// the proxy is saved only if it is ready
+fn serialize_proxy<S: Serializer>(state: &Arc<Mutex<ProxyState>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match *state.lock().unwrap() {
+        ProxyState::Saved(ref proxy) | ProxyState::Ready(ref proxy, _) => Some(proxy).serialize(serializer),
+        _ => None::<&Proxy>.serialize(serializer),
+    }
+}
+
+fn deserialize_proxy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<Mutex<ProxyState>>, D::Error> {
+    let proxy: Option<Proxy> = Deserialize::deserialize(deserializer)?;
+    Ok(Arc::new(Mutex::new(proxy.map(ProxyState::Saved).unwrap_or(ProxyState::NotGenerated))))
+}
+
+fn proxy_default() -> Arc<Mutex<ProxyState>> {
+    Arc::new(Mutex::new(ProxyState::NotGenerated))
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct VideoComponent {
     #[serde(flatten)]
     component: Component,
@@ -18,6 +63,33 @@ pub struct VideoComponent {
     #[serde(skip)]
     #[serde(deserialize_with = "Option::None")]
     pipeline: Option<gst::Pipeline>,
+
+    #[serde(default = "proxy_default")]
+    #[serde(serialize_with = "serialize_proxy", deserialize_with = "deserialize_proxy")]
+    proxy: Arc<Mutex<ProxyState>>,
+
+    #[serde(skip)]
+    frame_rate: Option<f64>,
+}
+
+// each clone has its own proxy state, while a proxy being generated is updated only in the original
+impl Clone for VideoComponent {
+    fn clone(&self) -> VideoComponent {
+        let proxy = match *self.proxy.lock().unwrap() {
+            ProxyState::Saved(ref proxy) => ProxyState::Saved(proxy.clone()),
+            ProxyState::Ready(ref proxy, ref pipeline) => ProxyState::Ready(proxy.clone(), pipeline.clone()),
+            ProxyState::Failed(ref err) => ProxyState::Failed(err.clone()),
+            ProxyState::NotGenerated | ProxyState::Generating(_) => ProxyState::NotGenerated,
+        };
+
+        VideoComponent {
+            component: self.component.clone(),
+            data_path: self.data_path.clone(),
+            pipeline: self.pipeline.clone(),
+            proxy: Arc::new(Mutex::new(proxy)),
+            frame_rate: self.frame_rate,
+        }
+    }
 }
 
 impl VideoComponent {
@@ -29,6 +101,107 @@ impl VideoComponent {
 
     fn load(&mut self) {
         self.pipeline = Some(VideoComponent::create_data(&self.data_path));
+        self.frame_rate = VideoComponent::detect_frame_rate(self.pipeline.as_ref().unwrap());
+
+        let mut state = self.proxy.lock().unwrap();
+        let saved = match *state {
+            ProxyState::Saved(ref proxy) => Some(proxy.clone()),
+            _ => None,
+        };
+        if let Some(proxy) = saved {
+            *state = if Path::new(&proxy.path).exists() {
+                let pipeline = VideoComponent::create_data(&proxy.path);
+                ProxyState::Ready(proxy, pipeline)
+            } else {
+                ProxyState::NotGenerated
+            };
+        }
+    }
+
+    // framerate of the caps negotiated by the sink
+    fn detect_frame_rate(pipeline: &gst::Pipeline) -> Option<f64> {
+        let appsink = pipeline.get_by_name("appsink")?;
+        let caps = appsink.get_static_pad("sink")?.get_current_caps()?;
+        let framerate = caps.get_structure(0)?.get::<gst::Fraction>("framerate")?;
+
+        if *framerate.denom() == 0 {
+            None
+        } else {
+            Some(*framerate.numer() as f64 / *framerate.denom() as f64)
+        }
+    }
+
+    fn proxy_path(&self, cache_dir: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.data_path.hash(&mut hasher);
+        Path::new(cache_dir).join(format!("proxy-{:016x}.avi", hasher.finish())).to_string_lossy().into_owned()
+    }
+
+    // size of the first frame, decoded by a pipeline of its own
+    fn original_size(src_path: &str) -> Result<(i32, i32), String> {
+        let pipeline = VideoComponent::create_data(src_path);
+        let original = VideoComponent::peek_pixbuf(&pipeline, 0 * gst::MSECOND).map_err(|_| "failed to decode the original".to_string());
+        let _ = pipeline.set_state(gst::State::Null);
+
+        original.map(|pixbuf| (pixbuf.get_width(), pixbuf.get_height()))
+    }
+
+    // decode the original and encode it in low resolution, blocking until finished
+    fn transcode<F: Fn(f64)>(src_path: &str, dest_path: &str, progress: F) -> Result<(), String> {
+        let pipeline = gst::Pipeline::new(None);
+        let src = gst::ElementFactory::make("filesrc", None).unwrap();
+        let decodebin = gst::ElementFactory::make("decodebin", None).unwrap();
+        let convert = gst::ElementFactory::make("videoconvert", None).unwrap();
+        let scale = gst::ElementFactory::make("videoscale", None).unwrap();
+        let capsfilter = gst::ElementFactory::make("capsfilter", None).unwrap();
+        let encoder = gst::ElementFactory::make("jpegenc", None).unwrap();
+        let avimux = gst::ElementFactory::make("avimux", None).unwrap();
+        let sink = gst::ElementFactory::make("filesink", None).unwrap();
+        src.set_property("location", &glib::Value::from(src_path)).unwrap();
+        sink.set_property("location", &glib::Value::from(dest_path)).unwrap();
+
+        let caps = gst::Caps::from_string(&format!("video/x-raw,height={}", PROXY_HEIGHT)).unwrap();
+        capsfilter.set_property("caps", &caps).unwrap();
+
+        pipeline.add_many(&[&src, &decodebin, &convert, &scale, &capsfilter, &encoder, &avimux, &sink]).unwrap();
+        gst::Element::link_many(&[&src, &decodebin]).unwrap();
+        gst::Element::link_many(&[&convert, &scale, &capsfilter, &encoder, &avimux, &sink]).unwrap();
+
+        let convert_ = convert.clone();
+        decodebin.connect_pad_added(move |_,src_pad| {
+            let sink_pad = convert_.get_static_pad("sink").unwrap();
+            let _ = src_pad.link(&sink_pad);
+        });
+
+        pipeline.set_state(gst::State::Playing).into_result().map_err(|t| format!("{:?}", t))?;
+
+        let bus = pipeline.get_bus().unwrap();
+        let mut result = Ok(());
+        loop {
+            use gst::MessageView;
+
+            if let Some(msg) = bus.timed_pop(100 * gst::MSECOND) {
+                match msg.view() {
+                    MessageView::Eos(..) => break,
+                    MessageView::Error(err) => {
+                        result = Err(format!("{:?}: {:?}", err.get_error(), err.get_debug()));
+                        break;
+                    }
+                    _ => (),
+                }
+            }
+
+            let position = pipeline.query_position::<gst::ClockTime>().and_then(|t| t.nseconds());
+            let duration = pipeline.query_duration::<gst::ClockTime>().and_then(|t| t.nseconds());
+            if let (Some(position), Some(duration)) = (position, duration) {
+                if duration > 0 {
+                    progress((position as f64 / duration as f64).min(1.0));
+                }
+            }
+        }
+
+        pipeline.set_state(gst::State::Null).into_result().map_err(|t| format!("{:?}", t))?;
+        result
     }
 
     fn create_data(uri: &str) -> gst::Pipeline {
@@ -65,8 +238,7 @@ impl VideoComponent {
         pipeline
     }
 
-    fn peek_pixbuf(&self, time: gst::ClockTime) -> Result<gdk_pixbuf::Pixbuf, String> {
-        let pipeline = self.pipeline.as_ref().unwrap();
+    fn peek_pixbuf(pipeline: &gst::Pipeline, time: gst::ClockTime) -> Result<gdk_pixbuf::Pixbuf, String> {
         pipeline.seek_simple(gst::SeekFlags::FLUSH, time).map_err(|t| t.to_string())?;
 
         let appsink = pipeline.get_by_name("appsink").unwrap();
@@ -85,7 +257,69 @@ impl HaveComponent for VideoComponent {
     }
 
     fn get_pixbuf(&self, time: gst::ClockTime) -> Option<gdk_pixbuf::Pixbuf> {
-        self.peek_pixbuf(time).ok()
+        VideoComponent::peek_pixbuf(self.pipeline.as_ref().unwrap(), time).ok()
+    }
+
+    fn frame_rate(&self) -> Option<f64> {
+        self.frame_rate
+    }
+
+    // frames of the proxy are smaller than the original, see source_size
+    fn get_preview_pixbuf(&self, time: gst::ClockTime) -> Option<gdk_pixbuf::Pixbuf> {
+        let proxy_frame = match *self.proxy.lock().unwrap() {
+            ProxyState::Ready(_, ref pipeline) => VideoComponent::peek_pixbuf(pipeline, time).ok(),
+            _ => None,
+        };
+
+        proxy_frame.or_else(|| self.get_pixbuf(time))
+    }
+
+    fn source_size(&self) -> Option<(i32, i32)> {
+        match *self.proxy.lock().unwrap() {
+            ProxyState::Saved(ref proxy) | ProxyState::Ready(ref proxy, _) => Some(proxy.size),
+            _ => None,
+        }
+    }
+
+    fn proxy_status(&self) -> ProxyStatus {
+        match *self.proxy.lock().unwrap() {
+            ProxyState::NotGenerated | ProxyState::Saved(_) => ProxyStatus::NotGenerated,
+            ProxyState::Generating(progress) => ProxyStatus::Generating(progress),
+            ProxyState::Ready(_, _) => ProxyStatus::Ready,
+            ProxyState::Failed(ref err) => ProxyStatus::Failed(err.clone()),
+        }
+    }
+
+    // transcode in background, where the progress can be seen in the proxy status
+    fn generate_proxy(&mut self, cache_dir: &str) -> Result<(), String> {
+        if let ProxyState::Generating(_) = *self.proxy.lock().unwrap() {
+            return Err("Proxy is being generated".to_string());
+        }
+
+        let (src_path, path, cache_dir) = (self.data_path.clone(), self.proxy_path(cache_dir), cache_dir.to_string());
+        let state = self.proxy.clone();
+        *state.lock().unwrap() = ProxyState::Generating(0.0);
+
+        thread::spawn(move || {
+            let result = VideoComponent::original_size(&src_path).and_then(|size| {
+                fs::create_dir_all(&cache_dir).map_err(|t| t.to_string()).and_then(|_| {
+                    VideoComponent::transcode(&src_path, &path, |progress| {
+                        *state.lock().unwrap() = ProxyState::Generating(progress);
+                    })
+                }).map(|_| size)
+            });
+
+            let next = match result {
+                Ok(size) => {
+                    let pipeline = VideoComponent::create_data(&path);
+                    ProxyState::Ready(Proxy { path: path, size: size }, pipeline)
+                },
+                Err(err) => ProxyState::Failed(err),
+            };
+            *state.lock().unwrap() = next;
+        });
+
+        Ok(())
     }
 
     fn tick(&self) -> Option<gdk_pixbuf::Pixbuf> {
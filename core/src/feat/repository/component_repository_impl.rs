@@ -1,19 +1,28 @@
 extern crate serde_json;
+extern crate gstreamer as gst;
 use spec::*;
 use feat::entity::*;
 use feat::repository::hashmap_impl::*;
+use feat::repository::interval_index::*;
 
 #[derive(Clone)]
 pub struct ComponentRepositoryImpl {
     repository: RepositoryHashMapImpl<ComponentExt>,
+    index: IntervalIndex,
 }
 
 impl ComponentRepositoryImpl {
     pub fn new() -> ComponentRepositoryImpl {
         ComponentRepositoryImpl {
             repository: RepositoryHashMapImpl::new(),
+            index: IntervalIndex::new(),
         }
     }
+
+    fn interval_of(entity: &ComponentExt) -> (u64, u64) {
+        let component = entity.component();
+        (component.start_time.nseconds().unwrap_or(0), component.end_time().nseconds().unwrap_or(0))
+    }
 }
 
 // I really do not like to write the following impl-s by hand,
@@ -21,7 +30,10 @@ impl ComponentRepositoryImpl {
 // since it conflicts the other impl for Repository...
 impl Repository<ComponentExt> for ComponentRepositoryImpl {
     fn create(&mut self, entity: ComponentExt) -> String {
-        self.repository.create(entity)
+        let (start, end) = ComponentRepositoryImpl::interval_of(&entity);
+        let key = self.repository.create(entity);
+        self.index.insert(&key, start, end);
+        key
     }
 
     fn get(&self, key: &str) -> &ComponentExt {
@@ -33,10 +45,13 @@ impl Repository<ComponentExt> for ComponentRepositoryImpl {
     }
 
     fn update(&mut self, key: String, entity: ComponentExt) {
+        let (start, end) = ComponentRepositoryImpl::interval_of(&entity);
+        self.index.insert(&key, start, end);
         self.repository.update(key, entity)
     }
 
     fn delete(&mut self, key: &str) {
+        self.index.remove(key);
         self.repository.delete(key)
     }
 }
@@ -49,7 +64,25 @@ impl MutRepository<ComponentExt> for ComponentRepositoryImpl {
 
 impl RepositoryLoader<ComponentExt> for ComponentRepositoryImpl {
     fn load_table(&mut self, value: Vec<Entity<ComponentExt, String>>) {
+        self.index.load(value.iter().map(|item| {
+            let (start, end) = ComponentRepositoryImpl::interval_of(&item.entity);
+            (item.id.clone(), start, end)
+        }));
         self.repository.load_table(value)
     }
 }
 
+impl ComponentRepository<ComponentExt> for ComponentRepositoryImpl {
+    fn active_at(&self, time: gst::ClockTime) -> Vec<&str> {
+        self.index.active_at(time.nseconds().unwrap_or(0))
+    }
+
+    fn overlapping(&self, from: gst::ClockTime, to: gst::ClockTime) -> Vec<&str> {
+        self.index.overlapping(from.nseconds().unwrap_or(0), to.nseconds().unwrap_or(0))
+    }
+
+    fn reindex(&mut self, key: &str) {
+        let (start, end) = ComponentRepositoryImpl::interval_of(self.repository.get(key));
+        self.index.insert(key, start, end);
+    }
+}
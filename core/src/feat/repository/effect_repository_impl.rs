@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use spec::*;
 use feat::repository::hashmap_impl::*;
 
@@ -12,6 +13,49 @@ impl EffectRepositoryImpl {
             repository: RepositoryHashMapImpl::new(),
         }
     }
+
+    // path holds the effects being bound, whose references are left unbound
+    fn bind<'a>(&self, effect: &'a Effect, time_of: &Fn(&str) -> EffectTime, path: &mut Vec<String>) -> Cow<'a, Effect> {
+        let references = effect.references();
+        if references.is_empty() {
+            return Cow::Borrowed(effect);
+        }
+
+        let mut effect = effect.clone();
+        for effect_id in references {
+            if path.contains(&effect_id) {
+                continue;
+            }
+
+            if let Some(referred) = self.repository.find(&effect_id) {
+                path.push(effect_id.clone());
+                let value = self.bind(referred, time_of, path).scalar(time_of(&effect_id));
+                path.pop();
+
+                effect.referred.insert(effect_id, value);
+            }
+        }
+
+        Cow::Owned(effect)
+    }
+
+    // depth-first search over references, where path holds the effects being visited
+    fn visit_references(&self, effect: &Effect, path: &mut Vec<String>) -> Result<(), String> {
+        for effect_id in effect.references() {
+            if let Some(index) = path.iter().position(|visiting| *visiting == effect_id) {
+                let mut cycle = path[index..].to_vec();
+                cycle.push(effect_id);
+                return Err(format!("Cyclic reference of effects: {}", cycle.join(" -> ")));
+            }
+
+            let referred = self.repository.find(&effect_id).ok_or(format!("No such effect: {}", effect_id))?;
+            path.push(effect_id);
+            self.visit_references(referred, path)?;
+            path.pop();
+        }
+
+        Ok(())
+    }
 }
 
 impl Repository<Effect> for EffectRepositoryImpl {
@@ -50,12 +94,19 @@ impl RepositoryLoader<Effect> for EffectRepositoryImpl {
 
 impl EffectRepository for EffectRepositoryImpl {
     fn create_intermed(&mut self, effect_id: &str, point: EffectPoint) {
-        let effect = self.repository.get_mut(effect_id);
-        effect.intervals.push(point);
+        self.repository.get_mut(effect_id).insert_keyframe(point);
+    }
+
+    fn value(&self, effect_id: &str, time: EffectTime, time_of: &Fn(&str) -> EffectTime) -> EffectValue {
+        self.resolve(effect_id, time_of).value(time)
+    }
+
+    fn resolve(&self, effect_id: &str, time_of: &Fn(&str) -> EffectTime) -> Cow<Effect> {
+        self.bind(self.repository.get(effect_id), time_of, &mut vec![effect_id.to_string()])
     }
 
-    fn value(&self, effect_id: &str, time: f32) -> f32 {
-        self.repository.get(effect_id).value(time)
+    fn validate_references(&self, effect_id: Option<&str>, effect: &Effect) -> Result<(), String> {
+        self.visit_references(effect, &mut effect_id.into_iter().map(|effect_id| effect_id.to_string()).collect())
     }
 }
 
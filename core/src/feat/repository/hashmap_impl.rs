@@ -15,6 +15,10 @@ impl<ENTITY> RepositoryHashMapImpl<ENTITY> {
             entities: HashMap::new(),
         }
     }
+
+    pub fn find(&self, index: &str) -> Option<&ENTITY> {
+        self.entities.get(index)
+    }
 }
 
 impl<ENTITY> Repository<ENTITY> for RepositoryHashMapImpl<ENTITY> {
@@ -0,0 +1,182 @@
+use std::cmp;
+use std::collections::HashMap;
+
+// Index of closed intervals [start, end] keyed by string.
+// Intervals are kept sorted by start, and a segment tree over them holds
+// the maximum end of each subtree so that queries skip intervals which end too early.
+#[derive(Clone)]
+pub struct IntervalIndex {
+    spans: HashMap<String, (u64, u64)>,
+    intervals: Vec<(u64, u64, String)>,
+    max_end: Vec<u64>,
+}
+
+impl IntervalIndex {
+    pub fn new() -> IntervalIndex {
+        IntervalIndex {
+            spans: HashMap::new(),
+            intervals: vec![],
+            max_end: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.intervals.len()
+    }
+
+    pub fn insert(&mut self, key: &str, start: u64, end: u64) {
+        self.remove_interval(key);
+
+        let index = self.lower_bound(start, key);
+        self.intervals.insert(index, (start, end, key.to_string()));
+        self.spans.insert(key.to_string(), (start, end));
+        self.build();
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if self.remove_interval(key) {
+            self.build();
+        }
+    }
+
+    pub fn load<I: Iterator<Item = (String, u64, u64)>>(&mut self, intervals: I) {
+        self.intervals = intervals.map(|(key, start, end)| (start, end, key)).collect();
+        // the same order as lower_bound searches in
+        self.intervals.sort_by(|x, y| (x.0, &x.2).cmp(&(y.0, &y.2)));
+        self.spans = self.intervals.iter().map(|&(start, end, ref key)| (key.clone(), (start, end))).collect();
+        self.build();
+    }
+
+    // keys of the intervals intersecting [from, to], ordered by start
+    pub fn overlapping(&self, from: u64, to: u64) -> Vec<&str> {
+        let mut result = vec![];
+        if self.intervals.len() > 0 {
+            let limit = self.upper_bound(to);
+            self.query(0, 0, self.intervals.len(), from, limit, &mut result);
+        }
+
+        result
+    }
+
+    pub fn active_at(&self, time: u64) -> Vec<&str> {
+        self.overlapping(time, time)
+    }
+
+    fn remove_interval(&mut self, key: &str) -> bool {
+        match self.spans.remove(key) {
+            Some((start, _)) => {
+                let index = self.lower_bound(start, key);
+                self.intervals.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // the first index whose (start, key) is not less than the given one
+    fn lower_bound(&self, start: u64, key: &str) -> usize {
+        let (mut lo, mut hi) = (0, self.intervals.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let (s, _, ref k) = self.intervals[mid];
+            if (s, k.as_str()) < (start, key) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        lo
+    }
+
+    // the number of intervals whose start is not greater than time
+    fn upper_bound(&self, time: u64) -> usize {
+        let (mut lo, mut hi) = (0, self.intervals.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.intervals[mid].0 <= time {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        lo
+    }
+
+    fn build(&mut self) {
+        let length = self.intervals.len();
+        self.max_end = vec![0; cmp::max(4 * length, 1)];
+        if length > 0 {
+            self.build_node(0, 0, length);
+        }
+    }
+
+    fn build_node(&mut self, node: usize, lo: usize, hi: usize) -> u64 {
+        let max_end = if hi - lo == 1 {
+            self.intervals[lo].1
+        } else {
+            let mid = (lo + hi) / 2;
+            cmp::max(self.build_node(2 * node + 1, lo, mid), self.build_node(2 * node + 2, mid, hi))
+        };
+
+        self.max_end[node] = max_end;
+        max_end
+    }
+
+    // collect the intervals in [lo, hi) such that index < limit and end >= from
+    fn query<'a>(&'a self, node: usize, lo: usize, hi: usize, from: u64, limit: usize, result: &mut Vec<&'a str>) {
+        if lo >= limit || self.max_end[node] < from {
+            return;
+        }
+
+        if hi - lo == 1 {
+            result.push(self.intervals[lo].2.as_str());
+            return;
+        }
+
+        let mid = (lo + hi) / 2;
+        self.query(2 * node + 1, lo, mid, from, limit, result);
+        self.query(2 * node + 2, mid, hi, from, limit, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(intervals: &[(&str, u64, u64)]) -> IntervalIndex {
+        let mut index = IntervalIndex::new();
+        index.load(intervals.iter().map(|&(key, start, end)| (key.to_string(), start, end)));
+        index
+    }
+
+    #[test]
+    fn remove_after_load_with_equal_starts() {
+        let mut index = load(&[("b", 0, 10), ("a", 0, 20)]);
+        index.remove("a");
+
+        assert_eq!(index.active_at(5), vec!["b"]);
+        assert_eq!(index.active_at(15), Vec::<&str>::new());
+        assert_eq!(index.len(), 1);
+    }
+
+    #[test]
+    fn insert_after_load_with_equal_starts() {
+        let mut index = load(&[("b", 0, 10), ("a", 0, 20), ("c", 5, 8)]);
+        index.insert("a", 30, 40);
+
+        assert_eq!(index.active_at(5), vec!["b", "c"]);
+        assert_eq!(index.active_at(35), vec!["a"]);
+    }
+
+    #[test]
+    fn query_overlapping() {
+        let index = load(&[("a", 0, 10), ("b", 5, 15), ("c", 20, 30)]);
+
+        assert_eq!(index.overlapping(0, 4), vec!["a"]);
+        assert_eq!(index.overlapping(10, 20), vec!["a", "b", "c"]);
+        assert_eq!(index.overlapping(16, 19), Vec::<&str>::new());
+        assert_eq!(index.active_at(30), vec!["c"]);
+    }
+}
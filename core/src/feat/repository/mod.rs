@@ -1,6 +1,9 @@
 mod hashmap_impl;
 pub use self::hashmap_impl::*;
 
+mod interval_index;
+pub use self::interval_index::*;
+
 mod component_repository_impl;
 pub use self::component_repository_impl::*;
 
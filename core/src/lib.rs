@@ -28,6 +28,7 @@ pub struct Madder {
     effect_repo: EffectRepositoryImpl,
     server: ApiServer,
     renderer: Option<AviRenderer>,
+    frame_cache: FrameCache,
 }
 
 unsafe impl Send for Madder {}
@@ -79,6 +80,12 @@ impl HaveApiServer for Madder {
     }
 }
 
+impl HaveFrameCache for Madder {
+    fn frame_cache(&self) -> &FrameCache {
+        &self.frame_cache
+    }
+}
+
 impl HavePresenter for Madder {}
 
 impl ProjectLoader for Madder {}
@@ -101,6 +108,7 @@ impl Madder {
             effect_repo: EffectRepositoryImpl::new(),
             server: ApiServer::new(),
             renderer: None,
+            frame_cache: FrameCache::new(),
         }
     }
 }
@@ -42,10 +42,6 @@ impl ParamHolder {
     fn find_as_u64(&self, param: &str) -> Result<u64, String> {
         self.find(param)?.parse().map_err(|x: ParseIntError| x.to_string())
     }
-
-    fn find_as_f32(&self, param: &str) -> Result<f32, String> {
-        self.find(param)?.parse().map_err(|x: ParseFloatError| x.to_string())
-    }
 }
 
 impl ApiServer {
@@ -74,6 +70,10 @@ impl ApiServer {
                 (Get, "mapper_get_component_attribute"),
                 (Update, "mapper_update_component_attribute"),
             ],
+            "/component/:component_id/proxy" => vec![
+                (Get, "mapper_get_component_proxy"),
+                (Create, "mapper_create_component_proxy"),
+            ],
             "/component/:component_id/effect" => vec![
                 (Get, "mapper_list_component_effect"),
                 (Create, "mapper_create_component_effect"),
@@ -87,6 +87,7 @@ impl ApiServer {
             ],
             "/effect/:effect_id" => vec![
                 (Get, "mapper_get_effect"),
+                (Update, "mapper_update_effect"),
             ],
             "/effect/:effect_id/intermed" => vec![
                 (Create, "mapper_create_effet_intermed"),
@@ -94,6 +95,22 @@ impl ApiServer {
             "/effect/:effect_id/value/:time" => vec![
                 (Get, "mapper_get_effect_value"),
             ],
+            "/layer" => vec![
+                (Get, "mapper_list_layer"),
+            ],
+            "/layer/:layer_index" => vec![
+                (Get, "mapper_get_layer"),
+                (Update, "mapper_update_layer"),
+            ],
+            "/layer/:layer_index/transition" => vec![
+                (Get, "mapper_list_layer_transition"),
+                (Create, "mapper_create_layer_transition"),
+            ],
+            "/layer/:layer_index/transition/:index" => vec![
+                (Get, "mapper_get_layer_transition"),
+                (Update, "mapper_update_layer_transition"),
+                (Delete, "mapper_delete_layer_transition"),
+            ],
             "/project/yaml" => vec![
                 (Get, "mapper_get_project_yaml"),
                 (Update, "mapper_update_project_yaml"),
@@ -101,9 +118,29 @@ impl ApiServer {
             "/project/tick" => vec![
                 (Create, "mapper_create_project_tick"),
             ],
+            "/project" => vec![
+                (Get, "mapper_get_project"),
+                (Update, "mapper_update_project"),
+            ],
+            "/timeline/:time" => vec![
+                (Get, "mapper_list_active_component"),
+            ],
+            "/timeline/:from/:to" => vec![
+                (Get, "mapper_list_overlapping_component"),
+            ],
+            "/cache" => vec![
+                (Get, "mapper_get_cache"),
+                (Delete, "mapper_delete_cache"),
+            ],
             "/screen/:time" => vec![
                 (Get, "mapper_get_screen"),
             ],
+            "/screen/:time/:quality" => vec![
+                (Get, "mapper_get_screen"),
+            ],
+            "/screen/:time/:quality/:scale" => vec![
+                (Get, "mapper_get_screen"),
+            ],
         };
 
         for (k,v) in mapper {
@@ -143,9 +180,11 @@ pub trait HaveApiServer : HavePresenter + ProjectLoader {
         match *matcher.handler {
             "mapper_create_component" => self.mapper_create_component(ParamHolder(matcher.params), entity),
             "mapper_create_component_effect" => self.mapper_create_component_effect(ParamHolder(matcher.params), entity),
+            "mapper_create_component_proxy" => self.mapper_create_component_proxy(ParamHolder(matcher.params), entity),
             "mapper_insert_component_effect" => self.mapper_insert_component_effect(ParamHolder(matcher.params), entity),
             "mapper_create_effet_intermed" => self.mapper_create_effect_intermed(ParamHolder(matcher.params), entity),
             "mapper_create_project_tick" => self.mapper_create_project_tick(ParamHolder(matcher.params), entity),
+            "mapper_create_layer_transition" => self.mapper_create_layer_transition(ParamHolder(matcher.params), entity),
             _ => unreachable!("{}", path),
         }
     }
@@ -156,13 +195,22 @@ pub trait HaveApiServer : HavePresenter + ProjectLoader {
             "mapper_list_component" => self.mapper_list_component(ParamHolder(matcher.params)),
             "mapper_get_component" => self.mapper_get_component(ParamHolder(matcher.params)),
             "mapper_get_component_attribute" => self.mapper_get_component_attribute(ParamHolder(matcher.params)),
+            "mapper_get_component_proxy" => self.mapper_get_component_proxy(ParamHolder(matcher.params)),
             "mapper_list_component_effect" => self.mapper_list_component_effect(ParamHolder(matcher.params)),
             "mapper_get_component_effect" => self.mapper_get_component_effect(ParamHolder(matcher.params)),
             "mapper_list_effect" => self.mapper_list_effect(ParamHolder(matcher.params)),
             "mapper_get_effect" => self.mapper_get_effect(ParamHolder(matcher.params)),
             "mapper_get_effect_value" => self.mapper_get_effect_value(ParamHolder(matcher.params)),
+            "mapper_list_layer" => self.mapper_list_layer(ParamHolder(matcher.params)),
+            "mapper_get_layer" => self.mapper_get_layer(ParamHolder(matcher.params)),
+            "mapper_list_layer_transition" => self.mapper_list_layer_transition(ParamHolder(matcher.params)),
+            "mapper_get_layer_transition" => self.mapper_get_layer_transition(ParamHolder(matcher.params)),
+            "mapper_get_project" => self.mapper_get_project(ParamHolder(matcher.params)),
             "mapper_get_project_yaml" => self.mapper_get_project_yaml(ParamHolder(matcher.params)),
             "mapper_get_screen" => self.mapper_get_screen(ParamHolder(matcher.params)),
+            "mapper_get_cache" => self.mapper_get_cache(ParamHolder(matcher.params)),
+            "mapper_list_active_component" => self.mapper_list_active_component(ParamHolder(matcher.params)),
+            "mapper_list_overlapping_component" => self.mapper_list_overlapping_component(ParamHolder(matcher.params)),
             _ => unreachable!("{}", path),
         }
     }
@@ -173,6 +221,10 @@ pub trait HaveApiServer : HavePresenter + ProjectLoader {
         match *matcher.handler {
             "mapper_update_component" => self.mapper_update_component(ParamHolder(matcher.params), entity),
             "mapper_update_component_attribute" => self.mapper_update_component_attribute(ParamHolder(matcher.params), entity),
+            "mapper_update_effect" => self.mapper_update_effect(ParamHolder(matcher.params), entity),
+            "mapper_update_layer" => self.mapper_update_layer(ParamHolder(matcher.params), entity),
+            "mapper_update_layer_transition" => self.mapper_update_layer_transition(ParamHolder(matcher.params), entity),
+            "mapper_update_project" => self.mapper_update_project(ParamHolder(matcher.params), entity),
             "mapper_update_project_yaml" => self.mapper_update_project_yaml(ParamHolder(matcher.params), entity),
             _ => unreachable!("{}", path),
         }
@@ -183,10 +235,59 @@ pub trait HaveApiServer : HavePresenter + ProjectLoader {
         let matcher = r.recognize(path)?;
         match *matcher.handler {
             "mapper_delete_component" => self.mapper_delete_component(ParamHolder(matcher.params)),
+            "mapper_delete_cache" => self.mapper_delete_cache(ParamHolder(matcher.params)),
+            "mapper_delete_layer_transition" => self.mapper_delete_layer_transition(ParamHolder(matcher.params)),
             _ => unreachable!("{}", path),
         }
     }
 
+    fn find_component_of_effect(&self, effect_id: &str) -> Option<String> {
+        self.component_repo().list().into_iter().find(|item| {
+            item.entity.component().effect.iter().any(|e| e == effect_id)
+        }).map(|item| item.id.to_string())
+    }
+
+    // components on a locked layer cannot be edited
+    fn ensure_editable(&self, component_id: &str) -> Result<(), String> {
+        match self.project().find_layer_of(component_id) {
+            Some(index) if self.project().list_layers()[index].locked => Err(format!("Layer {} is locked", index)),
+            _ => Ok(()),
+        }
+    }
+
+    // mask effects should refer to a mask of the component, and the effect of effect_id (if stored) should not be referred by itself
+    fn validate_effect(&self, component_id: Option<&str>, effect_id: Option<&str>, effect: &Effect) -> Result<(), String> {
+        Effect::validate_type(&effect.effect_type)?;
+        if let (Some(component_id), Some((index, name))) = (component_id, effect.mask_param()) {
+            let masks = &self.component_repo().get(component_id).component().masks;
+            masks.get(index).ok_or(format!("No such mask: {}", index))?.validate_param(name)?;
+        }
+
+        effect.validate_transitions()?;
+        effect.validate_values()?;
+        self.effect_repo().validate_references(effect_id, effect)?;
+
+        match effect.lut_name() {
+            Some(name) if self.project().lut(name).is_none() => Err(format!("No such LUT: {}", name)),
+            _ => Ok(()),
+        }
+    }
+
+    fn find_editable_layer(&self, layer_index: usize) -> Result<&Layer, String> {
+        let layer = self.project().list_layers().get(layer_index).ok_or(format!("No such layer: {}", layer_index))?;
+        if layer.locked {
+            return Err(format!("Layer {} is locked", layer_index));
+        }
+
+        Ok(layer)
+    }
+
+    fn validate_clip_transition(&self, layer_index: usize, transition: &ClipTransition) -> Result<(), String> {
+        let layer = self.find_editable_layer(layer_index)?;
+        layer.validate_transition(transition, |component_id| Some(self.component_repo().get(component_id).component()))
+            .map_err(|err| format!("Layer {}: {}", layer_index, err))
+    }
+
     fn mapper_list_component(&self, _: ParamHolder) -> Result<serde_json::Value, String> {
         Ok(json!(self.component_repo().list()))
     }
@@ -210,7 +311,13 @@ pub trait HaveApiServer : HavePresenter + ProjectLoader {
 
     fn mapper_get_component_attribute(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
         let component_id = params.find("component_id")?;
-        Ok(json!(self.component_repo().get(component_id).component().attributes[params.find("key")?]))
+        let key = params.find("key")?;
+        self.component_repo().get(component_id).component().get_attribute(key).ok_or(format!("No such attribute: {}", key))
+    }
+
+    fn mapper_get_component_proxy(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
+        let component_id = params.find("component_id")?;
+        Ok(json!(self.component_repo().get(component_id).proxy_status()))
     }
 
     fn mapper_get_component_effect(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
@@ -226,40 +333,151 @@ pub trait HaveApiServer : HavePresenter + ProjectLoader {
     }
 
     fn mapper_get_effect_value(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
+        // time in milliseconds from the start of the component
         let effect_id = params.find("effect_id")?;
-        let time = params.find_as_f32("time")?;
-        Ok(json!(self.effect_repo().value(effect_id, time)))
+        let time = params.find_as_u64("time")?;
+        let component_id = self.find_component_of_effect(effect_id).ok_or(format!("Effect {} is not used by any component", effect_id))?;
+        let component = self.component_repo().get(&component_id).component();
+        let current = EffectTime::new(time * gst::MSECOND, component.length);
+
+        // referred effects are at the same position in the project
+        let position = component.start_time + time * gst::MSECOND;
+        let time_of = |effect_id: &str| self.effect_time_at(effect_id, position).unwrap_or(current);
+
+        Ok(json!(self.effect_repo().value(effect_id, current, &time_of)))
     }
 
+    // quality and scale in the path override the preview options of the project
     fn mapper_get_screen(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
         let time: u64 = params.find_as_u64("time")?;
-        let encoded = base64::encode(&self.get_pixbuf(time * gst::MSECOND).save_to_bufferv("png", &[]).unwrap());
+        let mut options = self.project().preview.clone();
+        if let Ok(quality) = params.find("quality") {
+            options.quality = serde_json::from_value(json!(quality)).map_err(|t| t.to_string())?;
+        }
+        if let Ok(scale) = params.find("scale") {
+            options.scale = scale.parse().map_err(|x: ParseFloatError| x.to_string())?;
+        }
+
+        let encoded = base64::encode(&self.get_pixbuf_with(time * gst::MSECOND, &options).save_to_bufferv("png", &[]).unwrap());
         Ok(json!(format!("data:image/png;base64,{}", encoded)))
     }
 
+    fn mapper_list_active_component(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
+        let time = params.find_as_u64("time")?;
+        Ok(json!(self.component_repo().active_at(time * gst::MSECOND).into_iter().map(|component_id| {
+            Entity::new(component_id, self.component_repo().get(component_id))
+        }).collect::<Vec<_>>()))
+    }
+
+    fn mapper_list_overlapping_component(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
+        let from = params.find_as_u64("from")?;
+        let to = params.find_as_u64("to")?;
+        Ok(json!(self.component_repo().overlapping(from * gst::MSECOND, to * gst::MSECOND).into_iter().map(|component_id| {
+            Entity::new(component_id, self.component_repo().get(component_id))
+        }).collect::<Vec<_>>()))
+    }
+
+    fn mapper_get_cache(&self, _: ParamHolder) -> Result<serde_json::Value, String> {
+        Ok(json!(self.frame_cache().stats()))
+    }
+
+    fn mapper_get_project(&self, _: ParamHolder) -> Result<serde_json::Value, String> {
+        Ok(json!(self.project()))
+    }
+
+    fn mapper_list_layer(&self, _: ParamHolder) -> Result<serde_json::Value, String> {
+        Ok(json!(self.project().list_layers()))
+    }
+
+    fn mapper_get_layer(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
+        let layer_index = params.find_as_usize("layer_index")?;
+        let layer = self.project().list_layers().get(layer_index).ok_or(format!("No such layer: {}", layer_index))?;
+        Ok(json!(layer))
+    }
+
+    fn mapper_list_layer_transition(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
+        let layer_index = params.find_as_usize("layer_index")?;
+        let layer = self.project().list_layers().get(layer_index).ok_or(format!("No such layer: {}", layer_index))?;
+        Ok(json!(layer.transitions))
+    }
+
+    fn mapper_get_layer_transition(&self, params: ParamHolder) -> Result<serde_json::Value, String> {
+        let layer_index = params.find_as_usize("layer_index")?;
+        let index = params.find_as_usize("index")?;
+        let layer = self.project().list_layers().get(layer_index).ok_or(format!("No such layer: {}", layer_index))?;
+        Ok(json!(layer.transitions.get(index).ok_or(format!("No such transition: {}", index))?))
+    }
+
     fn mapper_get_project_yaml(&self, _: ParamHolder) -> Result<serde_json::Value, String> {
         Ok(json!(self.to_yaml_string().map_err(|t| t.to_string())?))
     }
 
     fn mapper_create_component(&mut self, _: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
-        let key = self.component_repo_mut().create(<Self as HaveComponentRepository>::new_from_json(entity));
-        self.project_mut().add_component_at(0, key);
+        self.find_editable_layer(0)?;
+
+        // placed in the project itself, which can not be referred by any composition
+        let component = <Self as HaveComponentRepository>::new_from_json(entity);
+        if let Some(name) = component.composition() {
+            if !self.project().compositions.contains_key(name) {
+                return Err(format!("No such composition: {}", name));
+            }
+        }
+
+        let key = self.component_repo_mut().create(component);
+        self.project_mut().add_component_at(0, key.clone());
+        self.frame_cache().invalidate_frames();
+
+        // proxies are generated in background on import, the progress and failure can be seen in the proxy status
+        let cache_dir = self.project().cache_dir.clone();
+        let component = self.component_repo_mut().get_mut(&key);
+        if component.proxy_status() == ProxyStatus::NotGenerated {
+            let _ = component.generate_proxy(&cache_dir);
+        }
 
         Ok(())
     }
 
+    // regenerate the proxy, e.g. after the original media has changed
+    fn mapper_create_component_proxy(&mut self, params: ParamHolder, _: serde_json::Value) -> Result<(), String> {
+        let component_id = params.find("component_id")?;
+        let cache_dir = self.project().cache_dir.clone();
+        let result = self.component_repo_mut().get_mut(component_id).generate_proxy(&cache_dir);
+        self.frame_cache().invalidate_component(component_id);
+
+        result
+    }
+
     fn mapper_create_component_effect(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
         let component_id = params.find("component_id")?;
-        let effect_id = self.effect_repo_mut().create(serde_json::from_value(entity).map_err(|t| t.to_string())?).to_string();
+        self.ensure_editable(component_id)?;
+        let mut effect: Effect = serde_json::from_value(entity).map_err(|t| t.to_string())?;
+        effect.parse_expression()?;
+        self.validate_effect(Some(component_id), None, &effect)?;
+        effect.normalize_keyframes();
+        let effect_id = self.effect_repo_mut().create(effect).to_string();
         let component = self.component_repo_mut().get_mut(component_id);
         component.component_mut().effect.push(effect_id);
+        self.frame_cache().invalidate_frames();
 
         Ok(())
     }
 
     fn mapper_create_effect_intermed(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
-        let effect_id = params.find("effect_id").unwrap();
-        self.effect_repo_mut().create_intermed(effect_id, serde_json::from_value(entity).unwrap());
+        let effect_id = params.find("effect_id")?;
+        if let Some(component_id) = self.find_component_of_effect(effect_id) {
+            self.ensure_editable(&component_id)?;
+        }
+
+        let point: EffectPoint = serde_json::from_value(entity).map_err(|t| t.to_string())?;
+        point.validate()?;
+
+        // the keyframe should be of the value type of the effect
+        let mut effect = self.effect_repo().get(effect_id).clone();
+        effect.insert_keyframe(point);
+        effect.validate_values()?;
+
+        self.effect_repo_mut().update(effect_id.to_string(), effect);
+        self.frame_cache().invalidate_frames();
 
         Ok(())
     }
@@ -276,42 +494,145 @@ pub trait HaveApiServer : HavePresenter + ProjectLoader {
         Ok(())
     }
 
+    fn mapper_create_layer_transition(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
+        let layer_index = params.find_as_usize("layer_index")?;
+        let transition: ClipTransition = serde_json::from_value(entity).map_err(|t| t.to_string())?;
+        self.validate_clip_transition(layer_index, &transition)?;
+
+        self.project_mut().layers[layer_index].transitions.push(transition);
+        self.frame_cache().invalidate_frames();
+
+        Ok(())
+    }
+
     fn mapper_insert_component_effect(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
         let component_id = params.find("component_id")?;
+        self.ensure_editable(component_id)?;
         let index = params.find_as_usize("index")?;
-        let effect_id = self.effect_repo_mut().create(serde_json::from_value(entity).map_err(|t| t.to_string())?).to_string();
+        let mut effect: Effect = serde_json::from_value(entity).map_err(|t| t.to_string())?;
+        effect.parse_expression()?;
+        self.validate_effect(Some(component_id), None, &effect)?;
+        effect.normalize_keyframes();
+        let effect_id = self.effect_repo_mut().create(effect).to_string();
         let component = self.component_repo_mut().get_mut(component_id);
         component.component_mut().effect.insert(index, effect_id);
+        self.frame_cache().invalidate_frames();
 
         Ok(())
     }
 
     fn mapper_delete_component(&mut self, params: ParamHolder) -> Result<(), String> {
         let component_id = params.find("component_id").unwrap();
+        self.ensure_editable(component_id)?;
         self.component_repo_mut().delete(component_id);
+        self.project_mut().remove_component(component_id);
+        self.frame_cache().invalidate_component(component_id);
+
+        Ok(())
+    }
+
+    fn mapper_delete_layer_transition(&mut self, params: ParamHolder) -> Result<(), String> {
+        let layer_index = params.find_as_usize("layer_index")?;
+        let index = params.find_as_usize("index")?;
+        if index >= self.find_editable_layer(layer_index)?.transitions.len() {
+            return Err(format!("No such transition: {}", index));
+        }
+
+        self.project_mut().layers[layer_index].transitions.remove(index);
+        self.frame_cache().invalidate_frames();
+
+        Ok(())
+    }
+
+    fn mapper_delete_cache(&mut self, _: ParamHolder) -> Result<(), String> {
+        self.frame_cache().clear();
 
         Ok(())
     }
 
     fn mapper_update_component(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
         let component_id = params.find("component_id")?;
+        self.ensure_editable(component_id)?;
         let component = self.component_repo_mut().get_mut(component_id);
-        component.component_mut().partial_update(entity.as_object().unwrap());
+        let old_length = component.component().length;
+        component.component_mut().partial_update(entity.as_object().ok_or("Component entity should be an object".to_string())?)?;
+
+        let new_length = component.component().length;
+        if component.component().rescale_keyframes && new_length != old_length {
+            for effect_id in component.component().effect.clone() {
+                self.effect_repo_mut().get_mut(&effect_id).rescale_keyframes(old_length, new_length);
+            }
+        }
+
+        self.component_repo_mut().reindex(component_id);
+        self.frame_cache().invalidate_component(component_id);
 
         Ok(())
     }
 
     fn mapper_update_component_attribute(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
         let component_id = params.find("component_id")?;
+        self.ensure_editable(component_id)?;
         let key = params.find("key")?;
-        let component = self.component_repo_mut().get_mut(component_id);
-        component.component_mut().attributes.insert(key.to_string(), entity);
+        let result = self.component_repo_mut().get_mut(component_id).component_mut().set_attribute(key, entity);
+        self.frame_cache().invalidate_component(component_id);
+
+        result
+    }
+
+    fn mapper_update_effect(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
+        let effect_id = params.find("effect_id")?;
+        let component_id = self.find_component_of_effect(effect_id);
+        if let Some(ref component_id) = component_id {
+            self.ensure_editable(component_id)?;
+        }
+
+        let mut effect = self.effect_repo().get(effect_id).clone();
+        effect.partial_update(entity.as_object().ok_or("Effect entity should be an object".to_string())?)?;
+        self.validate_effect(component_id.as_ref().map(|t| t.as_str()), Some(effect_id), &effect)?;
+
+        self.effect_repo_mut().update(effect_id.to_string(), effect);
+        self.frame_cache().invalidate_frames();
 
         Ok(())
     }
 
+    fn mapper_update_layer(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
+        let layer_index = params.find_as_usize("layer_index")?;
+        let result = {
+            let layer = self.project_mut().layers.get_mut(layer_index).ok_or(format!("No such layer: {}", layer_index))?;
+            layer.partial_update(entity.as_object().ok_or("Layer entity should be an object".to_string())?)
+        };
+        self.frame_cache().invalidate_frames();
+
+        result
+    }
+
+    fn mapper_update_layer_transition(&mut self, params: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
+        let layer_index = params.find_as_usize("layer_index")?;
+        let index = params.find_as_usize("index")?;
+        let transition: ClipTransition = serde_json::from_value(entity).map_err(|t| t.to_string())?;
+        self.validate_clip_transition(layer_index, &transition)?;
+        if index >= self.project().list_layers()[layer_index].transitions.len() {
+            return Err(format!("No such transition: {}", index));
+        }
+
+        self.project_mut().layers[layer_index].transitions[index] = transition;
+        self.frame_cache().invalidate_frames();
+
+        Ok(())
+    }
+
+    fn mapper_update_project(&mut self, _: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
+        let result = self.project_mut().partial_update(entity.as_object().ok_or("Project entity should be an object".to_string())?);
+        self.frame_cache().invalidate_frames();
+
+        result
+    }
+
     fn mapper_update_project_yaml(&mut self, _: ParamHolder, entity: serde_json::Value) -> Result<(), String> {
-        self.from_yaml_string(entity.as_str().unwrap()).unwrap();
+        self.from_yaml_string(entity.as_str().ok_or("Project yaml should be a string".to_string())?).map_err(|t| t.to_string())?;
+        self.frame_cache().clear();
 
         Ok(())
     }
@@ -8,6 +8,10 @@ extern crate gdk_pixbuf;
 extern crate cairo;
 
 use spec::*;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+const FRAMES_AHEAD: usize = 4;
 
 #[derive(Clone)]
 pub struct AviRenderer {
@@ -20,7 +24,51 @@ pub struct AviRenderer {
 }
 
 impl AviRenderer {
-    pub fn new(self_: impl HaveAviRenderer, uri: &str, audio_streams: Vec<(gst::ClockTime, Vec<gst::Element>)>, width: i32, height: i32, frames: i32, fps: i32) {
+    // pixels of pixbuf without the padding at the end of each row
+    fn pack_pixbuf(pixbuf: &gdk_pixbuf::Pixbuf) -> Vec<u8> {
+        let row_length = (pixbuf.get_width() * pixbuf.get_n_channels()) as usize;
+        let rowstride = pixbuf.get_rowstride() as usize;
+        let pixels = unsafe { pixbuf.get_pixels() };
+
+        (0..pixbuf.get_height() as usize).flat_map(|y| {
+            pixels[y * rowstride..y * rowstride + row_length].iter().cloned()
+        }).collect()
+    }
+
+    fn write_data(buffer: &mut gst::BufferRef, packed: &[u8]) {
+        let mut data = buffer.map_writable().unwrap();
+        data.as_mut_slice().copy_from_slice(packed);
+    }
+
+    // Spawn threads each of which renders every n-th frame ahead.
+    // Each worker owns a copy of the project with its own components, so that decoders and pixbufs
+    // are never shared between threads. Every frame is rendered once, thus workers cache nothing.
+    fn spawn_workers<R: HaveAviRenderer>(self_: &R, threads: usize, frames: i32, delta: u64) -> Vec<mpsc::Receiver<Vec<u8>>> {
+        (0..threads).map(|worker| {
+            let (sender, receiver) = mpsc::sync_channel(FRAMES_AHEAD);
+            let mut renderer = self_.clone();
+            renderer.reload_components();
+            renderer.frame_cache().set_capacity(0, 0);
+
+            thread::spawn(move || {
+                let options = renderer.project().export.clone();
+
+                let mut current = worker as i32;
+                while current <= frames {
+                    let pixbuf = renderer.get_pixbuf_with(current as u64 * delta * gst::MSECOND, &options);
+                    if sender.send(AviRenderer::pack_pixbuf(&pixbuf)).is_err() {
+                        break;
+                    }
+
+                    current += threads as i32;
+                }
+            });
+
+            receiver
+        }).collect()
+    }
+
+    pub fn new(self_: impl HaveAviRenderer, uri: &str, audio_streams: Vec<(gst::ClockTime, Vec<gst::Element>)>, width: i32, height: i32, frames: i32, fps: i32) -> Result<(), String> {
         let pipeline = gst::Pipeline::new(None);
         let appsrc = gst::ElementFactory::make("appsrc", None).unwrap();
         let videoconvert = gst::ElementFactory::make("videoconvert", None).unwrap();
@@ -42,12 +90,30 @@ impl AviRenderer {
         }
 
         let appsrc = appsrc.dynamic_cast::<gsta::AppSrc>().unwrap();
-        let info = gstv::VideoInfo::new(gstv::VideoFormat::Rgb, width as u32, height as u32).fps(gst::Fraction::new(fps,1)).build().unwrap();
+        let (format, channels) = if self_.project().background.has_alpha() {
+            (gstv::VideoFormat::Rgba, 4)
+        } else {
+            (gstv::VideoFormat::Rgb, 3)
+        };
+        let info = gstv::VideoInfo::new(format, width as u32, height as u32).fps(gst::Fraction::new(fps,1)).build().unwrap();
         appsrc.set_caps(&info.to_caps().unwrap());
         appsrc.set_property_format(gst::Format::Time);
 
         let mut current = 0;
         let delta = (1000 / fps) as u64;
+
+        // the pipeline stops with the frames rendered so far, and the error is returned at last
+        let error = Arc::new(Mutex::new(None));
+        let error_ = error.clone();
+
+        // with multiple threads, frames are rendered ahead and received in order
+        let threads = self_.project().export.threads;
+        let workers = if threads > 1 {
+            Some(AviRenderer::spawn_workers(&self_, threads, frames, delta))
+        } else {
+            None
+        };
+
         appsrc.set_callbacks(
             gsta::AppSrcCallbacks::new()
                 .need_data(move |appsrc,_| {
@@ -56,18 +122,24 @@ impl AviRenderer {
                         return;
                     }
 
-                    let pixbuf = self_.get_pixbuf(current as u64 * delta * gst::MSECOND);
-                    let mut buffer = gst::Buffer::with_size((width*height*3) as usize).unwrap();
+                    let packed = match workers {
+                        Some(ref receivers) => receivers[current as usize % receivers.len()].recv().ok(),
+                        None => Some(AviRenderer::pack_pixbuf(&self_.get_pixbuf_with(current as u64 * delta * gst::MSECOND, &self_.project().export))),
+                    };
+                    let packed = match packed {
+                        Some(packed) => packed,
+                        None => {
+                            *error_.lock().unwrap() = Some(format!("Worker has stopped at frame {}", current));
+                            current = frames + 1;
+                            let _ = appsrc.end_of_stream();
+                            return;
+                        },
+                    };
+                    let mut buffer = gst::Buffer::with_size((width*height*channels) as usize).unwrap();
                     {
                         let buffer = buffer.get_mut().unwrap();
                         buffer.set_pts(current as u64 * delta * gst::MSECOND);
-
-                        let mut data = buffer.map_writable().unwrap();
-                        let mut data = data.as_mut_slice();
-                        let pixels = unsafe { pixbuf.get_pixels() };
-
-                        use std::io::Write;
-                        data.write_all(pixels).unwrap();
+                        AviRenderer::write_data(buffer, &packed);
                     }
                     appsrc.push_buffer(buffer).into_result().unwrap();
                     current += 1;
@@ -84,11 +156,7 @@ impl AviRenderer {
             match msg.view() {
                 MessageView::Eos(..) => break,
                 MessageView::Error(err) => {
-                    println!(
-                        "Error from {:?}: {:?}",
-                        err.get_error(),
-                        err.get_debug(),
-                    );
+                    *error.lock().unwrap() = Some(format!("Error from {:?}: {:?}", err.get_error(), err.get_debug()));
                     break;
                 }
                 _ => (),
@@ -96,20 +164,20 @@ impl AviRenderer {
         }
 
         pipeline.set_state(gst::State::Null).into_result().unwrap();
+
+        let error = error.lock().unwrap().take();
+        match error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
     }
 
     pub fn render_step(&mut self, pixbuf: &gdk_pixbuf::Pixbuf) -> bool {
-        let mut buffer = gst::Buffer::with_size((self.size.0*self.size.1*3) as usize).unwrap();
+        let mut buffer = gst::Buffer::with_size((self.size.0*self.size.1*pixbuf.get_n_channels()) as usize).unwrap();
         {
             let buffer = buffer.get_mut().unwrap();
             buffer.set_pts(self.current as u64 * self.delta * gst::MSECOND);
-
-            let mut data = buffer.map_writable().unwrap();
-            let mut data = data.as_mut_slice();
-            let pixels = unsafe { pixbuf.get_pixels() };
-
-            use std::io::Write;
-            data.write_all(pixels).unwrap();
+            AviRenderer::write_data(buffer, &AviRenderer::pack_pixbuf(pixbuf));
         }
         self.appsrc.push_buffer(buffer).into_result().unwrap();
         self.current += 1;
@@ -122,12 +190,14 @@ impl AviRenderer {
     }
 }
 
-pub trait HaveAviRenderer : HavePresenter + Clone + Send + 'static {
+pub trait HaveAviRenderer : HavePresenter + ProjectLoader + Clone + Send + 'static {
     fn renderer(&self) -> &AviRenderer;
     fn renderer_mut(&mut self) -> &mut AviRenderer;
 
-    fn start_render(&mut self, uri: &str, frames: i32, fps: i32) {
+    // at the frame rate of the project, which motion blur and frame blending are based on as well
+    fn start_render(&mut self, uri: &str, frames: i32) -> Result<(), String> {
         let size = self.project().size;
+        let fps = self.project().fps;
         AviRenderer::new(self.clone(), uri, self.get_audio_streams(), size.0, size.1, frames, fps)
     }
 }
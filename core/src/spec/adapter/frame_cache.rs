@@ -0,0 +1,332 @@
+extern crate gdk_pixbuf;
+extern crate gstreamer as gst;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::Mutex;
+use spec::*;
+use util::*;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrameCacheStats {
+    pub component: CacheStats,
+    pub frame: CacheStats,
+}
+
+// pixbufs evicted in least-recently-used order, bounded by the total size of pixels
+struct PixbufTable<K> {
+    entries: HashMap<K, (gdk_pixbuf::Pixbuf, u64)>,
+
+    // keys by the time of their last use, oldest first
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    bytes: usize,
+    capacity: usize,
+    hits: u64,
+    misses: u64,
+}
+
+fn pixbuf_bytes(pixbuf: &gdk_pixbuf::Pixbuf) -> usize {
+    (pixbuf.get_rowstride() * pixbuf.get_height()) as usize
+}
+
+impl<K: Hash + Eq + Clone> PixbufTable<K> {
+    fn new(capacity: usize) -> PixbufTable<K> {
+        PixbufTable {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            bytes: 0,
+            capacity: capacity,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<gdk_pixbuf::Pixbuf> {
+        self.clock += 1;
+
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                self.recency.remove(&entry.1);
+                self.recency.insert(self.clock, key.clone());
+                entry.1 = self.clock;
+                self.hits += 1;
+                Some(entry.0.clone())
+            },
+            None => {
+                self.misses += 1;
+                None
+            },
+        }
+    }
+
+    fn insert(&mut self, key: K, pixbuf: gdk_pixbuf::Pixbuf) {
+        let bytes = pixbuf_bytes(&pixbuf);
+        if bytes > self.capacity {
+            return;
+        }
+
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        if let Some((old, used)) = self.entries.insert(key, (pixbuf, self.clock)) {
+            self.recency.remove(&used);
+            self.bytes -= pixbuf_bytes(&old);
+        }
+        self.bytes += bytes;
+        self.evict();
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    // remove least-recently-used entries until they fit in the capacity
+    fn evict(&mut self) {
+        while self.bytes > self.capacity {
+            let oldest = self.recency.values().next().cloned().unwrap();
+            self.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some((pixbuf, used)) = self.entries.remove(key) {
+            self.recency.remove(&used);
+            self.bytes -= pixbuf_bytes(&pixbuf);
+        }
+    }
+
+    fn retain<F: Fn(&K) -> bool>(&mut self, f: F) {
+        let keys = self.entries.keys().filter(|k| !f(k)).cloned().collect::<Vec<K>>();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.bytes = 0;
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            capacity: self.capacity,
+        }
+    }
+}
+
+// (component id, time in the component, decoded from proxy)
+type ComponentFrameKey = (String, u64, bool);
+
+// (position, quality, bits of scale, proxy)
+type FrameKey = (u64, RenderQuality, u64, bool);
+
+fn frame_key(position: gst::ClockTime, options: &RenderOptions) -> FrameKey {
+    (position.nseconds().unwrap_or(0), options.quality, options.scale.to_bits(), options.proxy)
+}
+
+struct FrameCacheTables {
+    components: PixbufTable<ComponentFrameKey>,
+    frames: PixbufTable<FrameKey>,
+
+    // components whose frames are decoded from their ready proxy
+    ready_proxies: HashSet<String>,
+}
+
+// Frames decoded by components and frames composed by the presenter.
+// Anything which changes the result of composition must invalidate the cache.
+// The cache keeps its own copies of pixbufs and returns copies of them,
+// so that callers may modify pixbufs in place without corrupting the cache.
+pub struct FrameCache {
+    tables: Mutex<FrameCacheTables>,
+}
+
+const COMPONENT_CAPACITY_DEFAULT: usize = 256 * 1024 * 1024;
+const FRAME_CAPACITY_DEFAULT: usize = 128 * 1024 * 1024;
+
+impl FrameCache {
+    pub fn new() -> FrameCache {
+        FrameCache::with_capacity(COMPONENT_CAPACITY_DEFAULT, FRAME_CAPACITY_DEFAULT)
+    }
+
+    pub fn with_capacity(component_capacity: usize, frame_capacity: usize) -> FrameCache {
+        FrameCache {
+            tables: Mutex::new(FrameCacheTables {
+                components: PixbufTable::new(component_capacity),
+                frames: PixbufTable::new(frame_capacity),
+                ready_proxies: HashSet::new(),
+            }),
+        }
+    }
+
+    // capacity 0 disables caching
+    pub fn set_capacity(&self, component_capacity: usize, frame_capacity: usize) {
+        let mut tables = self.tables.lock().unwrap();
+        tables.components.set_capacity(component_capacity);
+        tables.frames.set_capacity(frame_capacity);
+    }
+
+    pub fn get_component_frame(&self, component_id: &str, time: gst::ClockTime, proxy: bool) -> Option<gdk_pixbuf::Pixbuf> {
+        let key = (component_id.to_string(), time.nseconds().unwrap_or(0), proxy);
+        self.tables.lock().unwrap().components.get(&key).map(|pixbuf| writable_copy(&pixbuf, false))
+    }
+
+    pub fn insert_component_frame(&self, component_id: &str, time: gst::ClockTime, proxy: bool, pixbuf: &gdk_pixbuf::Pixbuf) {
+        let key = (component_id.to_string(), time.nseconds().unwrap_or(0), proxy);
+        self.tables.lock().unwrap().components.insert(key, writable_copy(pixbuf, false));
+    }
+
+    pub fn get_frame(&self, position: gst::ClockTime, options: &RenderOptions) -> Option<gdk_pixbuf::Pixbuf> {
+        self.tables.lock().unwrap().frames.get(&frame_key(position, options)).map(|pixbuf| writable_copy(&pixbuf, false))
+    }
+
+    pub fn insert_frame(&self, position: gst::ClockTime, options: &RenderOptions, pixbuf: &gdk_pixbuf::Pixbuf) {
+        self.tables.lock().unwrap().frames.insert(frame_key(position, options), writable_copy(pixbuf, false));
+    }
+
+    // the component itself has changed
+    pub fn invalidate_component(&self, component_id: &str) {
+        let mut tables = self.tables.lock().unwrap();
+        tables.components.retain(|key| key.0 != component_id);
+        tables.frames.clear();
+        tables.ready_proxies.remove(component_id);
+    }
+
+    // the proxy of the component is ready, where frames decoded from the original while it was generated are evicted once
+    pub fn proxy_ready(&self, component_id: &str) {
+        let mut tables = self.tables.lock().unwrap();
+        if tables.ready_proxies.insert(component_id.to_string()) {
+            tables.components.retain(|key| key.0 != component_id || !key.2);
+            tables.frames.retain(|key| !key.3);
+        }
+    }
+
+    // something affecting composition (effects, layers or the project) has changed
+    pub fn invalidate_frames(&self) {
+        self.tables.lock().unwrap().frames.clear();
+    }
+
+    pub fn clear(&self) {
+        let mut tables = self.tables.lock().unwrap();
+        tables.components.clear();
+        tables.frames.clear();
+        tables.ready_proxies.clear();
+    }
+
+    pub fn stats(&self) -> FrameCacheStats {
+        let tables = self.tables.lock().unwrap();
+
+        FrameCacheStats {
+            component: tables.components.stats(),
+            frame: tables.frames.stats(),
+        }
+    }
+}
+
+// A cloned FrameCache starts empty, so that clones do not share pixbufs
+impl Clone for FrameCache {
+    fn clone(&self) -> FrameCache {
+        let tables = self.tables.lock().unwrap();
+        FrameCache::with_capacity(tables.components.capacity, tables.frames.capacity)
+    }
+}
+
+pub trait HaveFrameCache {
+    fn frame_cache(&self) -> &FrameCache;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixbuf(value: u8) -> gdk_pixbuf::Pixbuf {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, true, 8, 2, 2);
+        pixbuf.fill(u32::from(value) << 24);
+        pixbuf
+    }
+
+    fn first_byte(pixbuf: &gdk_pixbuf::Pixbuf) -> u8 {
+        unsafe { pixbuf.get_pixels()[0] }
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let bytes = pixbuf_bytes(&pixbuf(0));
+        let mut table = PixbufTable::new(bytes * 2);
+
+        table.insert(1, pixbuf(1));
+        table.insert(2, pixbuf(2));
+        assert!(table.get(&1).is_some());
+        table.insert(3, pixbuf(3));
+
+        assert!(table.get(&1).is_some());
+        assert!(table.get(&2).is_none());
+        assert!(table.get(&3).is_some());
+        assert_eq!(table.stats().bytes, bytes * 2);
+    }
+
+    #[test]
+    fn replace_and_disable() {
+        let bytes = pixbuf_bytes(&pixbuf(0));
+        let mut table = PixbufTable::new(bytes * 2);
+
+        table.insert(1, pixbuf(1));
+        table.insert(1, pixbuf(2));
+        assert_eq!(table.stats().entries, 1);
+        assert_eq!(table.stats().bytes, bytes);
+
+        table.set_capacity(0);
+        assert_eq!(table.stats().entries, 0);
+        assert!(table.get(&1).is_none());
+    }
+
+    #[test]
+    fn cached_frames_are_not_shared() {
+        let cache = FrameCache::new();
+        let options = RenderOptions::preview();
+        let frame = pixbuf(1);
+
+        cache.insert_frame(0 * gst::SECOND, &options, &frame);
+        unsafe { frame.get_pixels()[0] = 2 };
+
+        let cached = cache.get_frame(0 * gst::SECOND, &options).unwrap();
+        assert_eq!(first_byte(&cached), 1);
+        unsafe { cached.get_pixels()[0] = 3 };
+        assert_eq!(first_byte(&cache.get_frame(0 * gst::SECOND, &options).unwrap()), 1);
+    }
+
+    #[test]
+    fn evict_frames_once_proxy_is_ready() {
+        let cache = FrameCache::new();
+        let (preview, export) = (RenderOptions::preview(), RenderOptions::export());
+        cache.insert_component_frame("video", 0 * gst::SECOND, true, &pixbuf(1));
+        cache.insert_component_frame("video", 0 * gst::SECOND, false, &pixbuf(1));
+        cache.insert_frame(0 * gst::SECOND, &preview, &pixbuf(1));
+        cache.insert_frame(0 * gst::SECOND, &export, &pixbuf(1));
+
+        cache.proxy_ready("video");
+        assert!(cache.get_component_frame("video", 0 * gst::SECOND, true).is_none());
+        assert!(cache.get_component_frame("video", 0 * gst::SECOND, false).is_some());
+        assert!(cache.get_frame(0 * gst::SECOND, &preview).is_none());
+        assert!(cache.get_frame(0 * gst::SECOND, &export).is_some());
+
+        // frames decoded from the ready proxy are kept
+        cache.insert_component_frame("video", 0 * gst::SECOND, true, &pixbuf(2));
+        cache.proxy_ready("video");
+        assert!(cache.get_component_frame("video", 0 * gst::SECOND, true).is_some());
+    }
+}
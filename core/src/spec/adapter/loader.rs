@@ -1,8 +1,19 @@
 extern crate serde_yaml;
+extern crate serde;
+use serde::de::Error;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use spec::*;
 
+// 1: positions of keyframes are in milliseconds, instead of [0,1]
+const PROJECT_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize)]
 pub struct ProjectYaml {
+    // files without version are of version 0
+    #[serde(default)]
+    version: u32,
+
     project: serde_yaml::Value,
 
     #[serde(default = "Vec::new")]
@@ -12,51 +23,348 @@ pub struct ProjectYaml {
     effects: Vec<serde_yaml::Value>,
 }
 
-pub trait ProjectLoader : HaveProject + HaveEffectRepository + HaveComponentRepository {
+pub trait ProjectLoader : HaveProject + HaveEffectRepository + HaveComponentRepository + Clone {
     fn to_yaml_string(&self) -> Result<String, serde_yaml::Error> {
         serde_yaml::to_string(&self.to_yaml()?)
     }
 
+    // components created again from their properties, so that they do not share decoders with the original
+    fn reload_components(&mut self) {
+        let components = self.component_repo().list().into_iter().map(|item| {
+            Entity {
+                id: item.id.to_string(),
+                entity: Self::new_from_json(serde_json::to_value(item.entity).unwrap()),
+            }
+        }).collect();
+        self.component_repo_mut().load_table(components);
+    }
+
     fn from_yaml_string(&mut self, value: &str) -> Result<(), serde_yaml::Error> {
         self.from_yaml(serde_yaml::from_str(value)?)
     }
 
     fn to_yaml(&self) -> Result<serde_yaml::Value, serde_yaml::Error> {
         serde_yaml::to_value(ProjectYaml {
+            version: PROJECT_VERSION,
             project: serde_yaml::to_value(self.project())?,
             components: self.component_repo().list().iter().map(|v| serde_yaml::to_value(v).unwrap()).collect(),
             effects: self.effect_repo().list().iter().map(|v| serde_yaml::to_value(v).unwrap()).collect(),
         })
     }
 
+    // the project is replaced only if the whole file is valid
     fn from_yaml(&mut self, value: serde_yaml::Value) -> Result<(), serde_yaml::Error> {
+        let mut loaded = self.clone();
+        loaded.load_yaml(value)?;
+        *self = loaded;
+
+        Ok(())
+    }
+
+    fn load_yaml(&mut self, value: serde_yaml::Value) -> Result<(), serde_yaml::Error> {
         let yaml = serde_yaml::from_value::<ProjectYaml>(value)?;
 
         {
             let project = serde_yaml::from_value::<Project>(yaml.project)?;
+            project.validate().map_err(serde_yaml::Error::custom)?;
             self.project_mut().layers = project.layers;
             self.project_mut().size = project.size as (i32,i32);
             self.project_mut().length = project.length;
+            self.project_mut().preview = project.preview;
+            self.project_mut().export = project.export;
+            self.project_mut().background = project.background;
+            self.project_mut().cache_dir = project.cache_dir;
+            self.project_mut().luts = project.luts;
+            self.project_mut().output_lut = project.output_lut;
+            self.project_mut().fps = project.fps;
+            self.project_mut().motion_blur = project.motion_blur;
+            self.project_mut().compositions = project.compositions;
+            self.project_mut().load_luts().map_err(serde_yaml::Error::custom)?;
+        }
+
+        let components = yaml.components.into_iter().map(|v| {
+            let entity = serde_yaml::from_value::<Entity<serde_yaml::Value, String>>(v)?;
+            let component = <Self as HaveComponentRepository>::new_from_json(
+                serde_yaml::from_value(entity.entity)?
+            );
+
+            Ok(Entity {
+                id: entity.id,
+                entity: component,
+            })
+        }).collect::<Result<Vec<_>, serde_yaml::Error>>()?;
+        self.component_repo_mut().load_table(components);
+
+        let mut effects = yaml.effects;
+        if yaml.version < 1 {
+            // an effect shared by components of different lengths is placed in the shortest one
+            let mut lengths = HashMap::new();
+            for item in self.component_repo().list() {
+                let length = item.entity.component().length.mseconds().unwrap_or(0);
+                for effect_id in &item.entity.component().effect {
+                    let shortest = lengths.entry(effect_id.clone()).or_insert(length);
+                    *shortest = cmp::min(*shortest, length);
+                }
+            }
+
+            for effect in &mut effects {
+                migrate_keyframes(effect, &lengths);
+            }
         }
 
-        self.component_repo_mut().load_table(
-            yaml.components.into_iter().map(|v| {
-                let entity = serde_yaml::from_value::<Entity<serde_yaml::Value, String>>(v).unwrap();
-                let component = <Self as HaveComponentRepository>::new_from_json(
-                    serde_yaml::from_value(entity.entity).unwrap()
-                );
+        let effects = effects.into_iter().map(|v| {
+            let mut entity = serde_yaml::from_value::<Entity<Effect, String>>(v)?;
+            entity.entity.normalize_keyframes();
+            entity.entity.parse_expression().map_err(|t| serde_yaml::Error::custom(format!("Effect {}: {}", entity.id, t)))?;
+            Ok(entity)
+        }).collect::<Result<Vec<_>, serde_yaml::Error>>()?;
+        self.effect_repo_mut().load_table(effects);
 
-                Entity {
-                    id: entity.id,
-                    entity: component,
+        // transitions and values should be valid, and lut effects should refer to LUTs in the project
+        for item in self.effect_repo().list() {
+            item.entity.validate_transitions()
+                .and_then(|_| item.entity.validate_values())
+                .and_then(|_| self.effect_repo().validate_references(Some(item.id), item.entity))
+                .map_err(|t| serde_yaml::Error::custom(format!("Effect {}: {}", item.id, t)))?;
+            if let Some(name) = item.entity.lut_name() {
+                if self.project().lut(name).is_none() {
+                    return Err(serde_yaml::Error::custom(format!("Effect {} refers to no such LUT: {}", item.id, name)));
                 }
-            }).collect()
-        );
-        self.effect_repo_mut().load_table(
-            yaml.effects.into_iter().map(|v| serde_yaml::from_value(v).unwrap()).collect()
-        );
+            }
+        }
+
+        self.validate_compositions().map_err(serde_yaml::Error::custom)?;
+
+        self.validate_transitions(&self.project().layers).map_err(serde_yaml::Error::custom)?;
+        for (name, composition) in &self.project().compositions {
+            self.validate_transitions(&composition.layers).map_err(|err| serde_yaml::Error::custom(format!("Composition {}: {}", name, err)))?;
+        }
+
+        Ok(())
+    }
+
+    // transitions should be between adjacent clips on each layer, as the API requires
+    fn validate_transitions(&self, layers: &[Layer]) -> Result<(), String> {
+        let components = self.component_repo().list();
+        let component = |component_id: &str| {
+            components.iter().find(|item| item.id == component_id).map(|item| item.entity.component())
+        };
+
+        for (index, layer) in layers.iter().enumerate() {
+            for transition in &layer.transitions {
+                layer.validate_transition(transition, &component).map_err(|err| format!("Transition on layer {}: {}", index, err))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // names of the compositions referred by components on the layers
+    fn referred_compositions<'a>(&'a self, layers: &'a [Layer]) -> Vec<&'a str> {
+        layers.iter().flat_map(|layer| layer.list().iter()).filter_map(|component_id| {
+            self.component_repo().get(component_id).composition()
+        }).collect()
+    }
+
+    // composition components should refer to existing compositions, without any cyclic reference
+    fn validate_compositions(&self) -> Result<(), String> {
+        let refers = |name: &str| match self.project().compositions.get(name) {
+            Some(composition) => Ok(self.referred_compositions(&composition.layers)),
+            None => Err(format!("No such composition: {}", name)),
+        };
+
+        for name in self.referred_compositions(&self.project().layers) {
+            refers(name)?;
+        }
+
+        let mut done = HashSet::new();
+        for name in self.project().compositions.keys() {
+            visit_composition(name, &refers, &mut vec![], &mut done)?;
+        }
 
         Ok(())
     }
 }
 
+// positions of keyframes from [0,1] to milliseconds, by the length of the component using the effect.
+// keyframes of an effect used by no component have no length to be placed in, thus dropped
+fn migrate_keyframes(effect: &mut serde_yaml::Value, lengths: &HashMap<String, u64>) {
+    let id = effect.get("id").and_then(|id| id.as_str()).unwrap_or("").to_string();
+    let length = lengths.get(&id).cloned();
+    let intervals = effect.as_mapping_mut()
+        .and_then(|effect| effect.get_mut(&serde_yaml::Value::String("intervals".to_string())))
+        .and_then(|intervals| intervals.as_sequence_mut());
+
+    if let Some(intervals) = intervals {
+        let length = match length {
+            Some(length) => length,
+            None => {
+                if !intervals.is_empty() {
+                    eprintln!("Warning: keyframes of effect {} are dropped, since it is used by no component", id);
+                    intervals.clear();
+                }
+                return;
+            },
+        };
+
+        for point in intervals.iter_mut().filter_map(|point| point.as_mapping_mut()) {
+            let key = serde_yaml::Value::String("position".to_string());
+            let position = point.get(&key).and_then(|position| position.as_f64()).unwrap_or(0.0);
+            point.insert(key, serde_yaml::Value::from((position * length as f64).round() as u64));
+        }
+    }
+}
+
+// depth-first search over compositions, where path holds the ones being visited
+fn visit_composition<'a, F>(name: &'a str, refers: &F, path: &mut Vec<&'a str>, done: &mut HashSet<&'a str>) -> Result<(), String>
+    where F: Fn(&'a str) -> Result<Vec<&'a str>, String>
+{
+    if done.contains(name) {
+        return Ok(());
+    }
+    if let Some(index) = path.iter().position(|visiting| *visiting == name) {
+        let mut cycle = path[index..].to_vec();
+        cycle.push(name);
+        return Err(format!("Cyclic reference of compositions: {}", cycle.join(" -> ")));
+    }
+
+    path.push(name);
+    for child in refers(name)? {
+        visit_composition(child, refers, path, done)?;
+    }
+    path.pop();
+    done.insert(name);
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effect(id: &str) -> serde_yaml::Value {
+        serde_yaml::from_str(&format!("id: {}\nintervals:\n  - position: 0.5\n  - position: 1.0\n", id)).unwrap()
+    }
+
+    fn positions(effect: &serde_yaml::Value) -> Vec<u64> {
+        effect.get("intervals").unwrap().as_sequence().unwrap().iter().map(|point| {
+            point.get("position").unwrap().as_u64().unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn migrate_by_component_length() {
+        let mut value = effect("blur");
+        migrate_keyframes(&mut value, &hashmap!{ "blur".to_string() => 2000 });
+
+        assert_eq!(positions(&value), vec![1000, 2000]);
+    }
+
+    #[test]
+    fn migrate_drops_keyframes_of_unused_effects() {
+        let mut value = effect("unused");
+        migrate_keyframes(&mut value, &HashMap::new());
+
+        assert_eq!(positions(&value), Vec::<u64>::new());
+    }
+
+    fn visit_all(graph: &HashMap<&'static str, Vec<&'static str>>) -> Result<(), String> {
+        let refers = |name: &'static str| graph.get(name).cloned().ok_or(format!("No such composition: {}", name));
+        let mut done = HashSet::new();
+        let mut names = graph.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+
+        for name in names {
+            visit_composition(name, &refers, &mut vec![], &mut done)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn visit_compositions() {
+        // shared by two compositions, without any cycle
+        assert!(visit_all(&hashmap!{ "a" => vec!["b", "c"], "b" => vec!["c"], "c" => vec![] }).is_ok());
+
+        assert_eq!(visit_all(&hashmap!{ "a" => vec!["b"], "b" => vec!["c"], "c" => vec!["a"] }), Err("Cyclic reference of compositions: a -> b -> c -> a".to_string()));
+        assert_eq!(visit_all(&hashmap!{ "a" => vec!["a"] }), Err("Cyclic reference of compositions: a -> a".to_string()));
+        assert_eq!(visit_all(&hashmap!{ "a" => vec!["d"] }), Err("No such composition: d".to_string()));
+    }
+
+    fn composition_project(inner: &str) -> String {
+        format!(r#"
+version: 1
+project:
+  layers:
+    - components: [outer]
+  size: [640, 480]
+  length: 1000
+  position: 0
+  compositions:
+    scene:
+      layers:
+        - components: [inner]
+      size: [320, 240]
+      length: 1000
+components:
+  - id: outer
+    component_type: Composition
+    start_time: 0
+    length: 1000
+    composition: scene
+  - id: inner
+    component_type: Composition
+    start_time: 0
+    length: 1000
+    composition: {}
+"#, inner)
+    }
+
+    #[test]
+    fn reject_cyclic_compositions() {
+        let mut madder = ::Madder::new();
+        let err = madder.from_yaml_string(&composition_project("scene")).unwrap_err().to_string();
+        assert!(err.contains("Cyclic reference of compositions: scene -> scene"), "{}", err);
+
+        let err = madder.from_yaml_string(&composition_project("nowhere")).unwrap_err().to_string();
+        assert!(err.contains("No such composition: nowhere"), "{}", err);
+
+        // the project is left as it was
+        assert!(madder.project().compositions.is_empty());
+        assert_eq!(madder.component_repo().list().len(), 0);
+    }
+
+    #[test]
+    fn active_components_of_loaded_project() {
+        let mut madder = ::Madder::new();
+        madder.from_yaml_string(r#"
+version: 1
+project:
+  layers:
+    - components: [intro, title, outro]
+  size: [640, 480]
+  length: 1000
+  position: 0
+components:
+  - id: intro
+    component_type: Adjustment
+    start_time: 0
+    length: 500
+  - id: title
+    component_type: Adjustment
+    start_time: 200
+    length: 600
+  - id: outro
+    component_type: Adjustment
+    start_time: 1200
+    length: 300
+"#).unwrap();
+
+        let repo = madder.component_repo();
+        assert_eq!(repo.active_at(0 * gst::MSECOND), vec!["intro"]);
+        assert_eq!(repo.active_at(300 * gst::MSECOND), vec!["intro", "title"]);
+        assert_eq!(repo.active_at(900 * gst::MSECOND), Vec::<&str>::new());
+        // components after the project length are still loaded
+        assert_eq!(repo.active_at(1300 * gst::MSECOND), vec!["outro"]);
+    }
+}
@@ -4,6 +4,9 @@ pub use self::api_server::*;
 mod presenter;
 pub use self::presenter::*;
 
+mod frame_cache;
+pub use self::frame_cache::*;
+
 mod loader;
 pub use self::loader::*;
 
@@ -1,59 +1,766 @@
 extern crate gdk_pixbuf;
 extern crate gstreamer as gst;
 use gdk_pixbuf::prelude::*;
+use gst::prelude::*;
+use std::borrow::Cow;
 use std::cmp;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
 use spec::*;
+use util::*;
 
-pub trait HavePresenter : HaveProject + HaveComponentRepository + HaveEffectRepository {
+// a frame of component, ready to be composited
+pub struct ComponentFrame {
+    pub pixbuf: gdk_pixbuf::Pixbuf,
+    pub geometry: Geometry,
+    pub blend_mode: BlendMode,
+}
+
+pub trait HavePresenter : HaveProject + HaveComponentRepository + HaveEffectRepository + HaveFrameCache {
     fn get_pixbuf(&self, position: gst::ClockTime) -> gdk_pixbuf::Pixbuf {
-        let pixbuf = gdk_pixbuf::Pixbuf::new(
-            gdk_pixbuf::Colorspace::Rgb,
-            false,
-            8,
-            self.project().size.0,
-            self.project().size.1
-        );
+        self.get_pixbuf_with(position, &self.project().preview)
+    }
 
-        for p in unsafe { pixbuf.get_pixels().chunks_mut(3) } {
-            p[0] = 0;
-            p[1] = 0;
-            p[2] = 0;
-        }
-
-        for layer in self.project().list_layers().iter().rev() {
-            for component in layer.list().iter().map(|component_id| {
-                self.component_repo().get(component_id)
-            }).filter(|component| {
-                component.component().start_time <= position &&
-                    position <= component.component().end_time()
-            }) {
-                if let Some(dest) = component.get_pixbuf(position - component.component().start_time) {
-                    let coordinate = (0,0);
-                    let scale = (1.0,1.0);
-                    let alpha = 255;
-
-                    &dest.composite(
-                        &pixbuf, coordinate.0, coordinate.1,
-                        cmp::min(dest.get_width(), self.project().size.0 - coordinate.0),
-                        cmp::min(dest.get_height(), self.project().size.1 - coordinate.1),
-                        coordinate.0.into(), coordinate.1.into(),
-                        scale.0, scale.1,
-                        gdk_pixbuf::InterpType::Nearest, alpha);
+    fn get_pixbuf_with(&self, position: gst::ClockTime, options: &RenderOptions) -> gdk_pixbuf::Pixbuf {
+        // proxies are generated in background, replacing frames decoded from the originals meanwhile
+        if options.proxy {
+            for item in self.component_repo().list() {
+                if item.entity.proxy_status() == ProxyStatus::Ready {
+                    self.frame_cache().proxy_ready(item.id);
                 }
             }
         }
 
+        if let Some(pixbuf) = self.frame_cache().get_frame(position, options) {
+            return pixbuf;
+        }
+
+        let pixbuf = self.compose_pixbuf(position, options);
+        self.frame_cache().insert_frame(position, options, &pixbuf);
         pixbuf
     }
 
+    fn get_component_pixbuf(&self, component_id: &str, time: gst::ClockTime, options: &RenderOptions) -> Option<gdk_pixbuf::Pixbuf> {
+        // a composition changes with the components in it, thus not cached as a component
+        if let Some(name) = self.component_repo().get(component_id).composition() {
+            return self.project().compositions.get(name).map(|composition| {
+                self.compose_layers(composition.visible_layers(), composition.size, &composition.background, None, time, options)
+            });
+        }
+
+        if let Some(pixbuf) = self.frame_cache().get_component_frame(component_id, time, options.proxy) {
+            return Some(pixbuf);
+        }
+
+        let component = self.component_repo().get(component_id);
+        let pixbuf = if options.proxy {
+            component.get_preview_pixbuf(time)?
+        } else {
+            component.get_pixbuf(time)?
+        };
+        self.frame_cache().insert_component_frame(component_id, time, options.proxy, &pixbuf);
+        Some(pixbuf)
+    }
+
+    // the source frame at the local time, retimed by the speed of the component
+    fn get_retimed_pixbuf(&self, component_id: &str, local_time: gst::ClockTime, options: &RenderOptions) -> Option<gdk_pixbuf::Pixbuf> {
+        let component = self.component_repo().get(component_id);
+        let speed = component.component().speed.max(0.0);
+        let media_time = local_time.nseconds().unwrap_or(0) as f64 * speed;
+
+        let blending = match component.frame_rate() {
+            Some(fps) if component.component().frame_blending && fps > 0.0 && (speed != 1.0 || (fps - self.project().fps as f64).abs() > 0.01) => Some(fps),
+            _ => None,
+        };
+        let fps = match blending {
+            Some(fps) => fps,
+            None => return self.get_component_pixbuf(component_id, (media_time as u64) * gst::NSECOND, options),
+        };
+
+        // blend the source frames on both sides of the media time by its distance to them
+        let frame = media_time * fps / 1_000_000_000.0;
+        let weight = frame - frame.floor();
+        let frame_time = |index: f64| ((index / fps * 1_000_000_000.0) as u64) * gst::NSECOND;
+
+        let pixbuf = self.get_component_pixbuf(component_id, frame_time(frame.floor()), options)?;
+        let alpha = (weight * 255.0).round() as i32;
+        if alpha == 0 {
+            return Some(pixbuf);
+        }
+
+        let next = match self.get_component_pixbuf(component_id, frame_time(frame.floor() + 1.0), options) {
+            Some(next) => next,
+            None => return Some(pixbuf),
+        };
+
+        let blended = writable_copy(&pixbuf, false);
+        composite_geometry(&blended, &next, &Geometry {
+            coordinate: (0, 0),
+            scale: (1.0, 1.0),
+            alpha: alpha,
+        }, gdk_pixbuf::InterpType::Nearest);
+
+        Some(blended)
+    }
+
+    // the time of a component using the effect at the position, preferring one active at the position
+    fn effect_time_at(&self, effect_id: &str, position: gst::ClockTime) -> Option<EffectTime> {
+        let uses = |component_id: &&str| self.component_repo().get(component_id).component().effect.iter().any(|id| id == effect_id);
+        let active = self.component_repo().active_at(position).into_iter().find(&uses);
+        let component_id = active.or_else(|| {
+            self.component_repo().list().into_iter().map(|item| item.id).filter(&uses).min()
+        })?;
+
+        let component = self.component_repo().get(component_id).component();
+        Some(component.effect_time(component.local_time(position)))
+    }
+
+    // effects of the component at the position, where effects referred by expressions are evaluated
+    // at the time of the components using them, or of this component if none uses them
+    fn resolve_effects(&self, component: &Component, position: gst::ClockTime) -> Vec<Cow<Effect>> {
+        let current = component.effect_time(component.local_time(position));
+        let time_of = |effect_id: &str| self.effect_time_at(effect_id, position).unwrap_or(current);
+
+        component.effect.iter().map(|effect_id| self.effect_repo().resolve(effect_id, &time_of)).collect()
+    }
+
+    // pixel effects in order, where lut effects refer to LUTs of the project
+    fn apply_effects(&self, pixbuf: gdk_pixbuf::Pixbuf, effects: &[&Effect], current: EffectTime) -> gdk_pixbuf::Pixbuf {
+        effects.iter().fold(pixbuf, |pixbuf, effect| match effect.lut(current) {
+            Some((name, intensity)) => match self.project().lut(name) {
+                Some(lut) => lut.apply_pixbuf(pixbuf, intensity),
+                None => pixbuf,
+            },
+            None => effect.effect_on_pixbuf(pixbuf, current),
+        })
+    }
+
+    // apply effects of the adjustment components active on the layer to the canvas, which holds the layers below
+    fn apply_adjustments(&self, canvas: &gdk_pixbuf::Pixbuf, layer: &Layer, active: &HashSet<&str>, position: gst::ClockTime, interp_type: gdk_pixbuf::InterpType) {
+        for component_id in layer.list().iter().filter(|component_id| active.contains(component_id.as_str())) {
+            let component = self.component_repo().get(component_id);
+            if !component.is_adjustment() {
+                continue;
+            }
+
+            let current = component.component().effect_time(component.component().local_time(position));
+            let effects = self.resolve_effects(component.component(), position);
+            let effects = effects.iter().map(|effect| effect.as_ref()).collect::<Vec<&Effect>>();
+            // on a copy, since the canvas is read while effects are applied
+            let adjusted = self.apply_effects(writable_copy(canvas, false), &effects, current);
+
+            // effects which change the size, such as rotate, can not be applied to the canvas
+            if adjusted.get_width() != canvas.get_width() || adjusted.get_height() != canvas.get_height() {
+                continue;
+            }
+
+            // the alpha of the geometry is the opacity of the adjustment
+            let geometry = effects.iter().fold(component.component().geometry.clone(), |geometry, effect| {
+                effect.effect_on_geometry(geometry, current)
+            });
+            if geometry.alpha >= 255 {
+                adjusted.copy_area(0, 0, canvas.get_width(), canvas.get_height(), canvas, 0, 0);
+            } else {
+                composite_geometry(canvas, &adjusted, &Geometry {
+                    coordinate: (0, 0),
+                    scale: (1.0, 1.0),
+                    alpha: geometry.alpha,
+                }, interp_type);
+            }
+        }
+    }
+
+    // a frame of the component at the position, with its effects applied
+    fn render_component(&self, size: (i32, i32), component_id: &str, layer: &Layer, position: gst::ClockTime, options: &RenderOptions) -> Option<ComponentFrame> {
+        let component = self.component_repo().get(component_id);
+        let local_time = component.component().local_time(position);
+        let current = component.component().effect_time(local_time);
+        let effects = self.resolve_effects(component.component(), position);
+        let effects = effects.iter().map(|effect| effect.as_ref()).collect::<Vec<&Effect>>();
+
+        // masks are in the coordinate of the original, thus applied before other effects
+        let masks = effects.iter().fold(component.component().masks.clone(), |masks, effect| effect.effect_on_masks(masks, current));
+        let pixbuf = self.get_retimed_pixbuf(component_id, local_time, options)?;
+
+        // proxy frames are smaller than the original, which masks, crop and geometry are in
+        let proxy_scale = match component.source_size() {
+            Some(size) if options.proxy && size.0 > 0 => pixbuf.get_width() as f64 / size.0 as f64,
+            _ => 1.0,
+        };
+
+        let pixbuf = Mask::mask_pixbuf(pixbuf, &masks, proxy_scale);
+        let crop = effects.iter().fold(component.component().crop.clone(), |crop, effect| effect.effect_on_crop(crop, current));
+        let (pixbuf, offset) = crop.scaled(proxy_scale).crop_pixbuf(pixbuf)?;
+        let pixbuf = self.apply_effects(pixbuf, &effects, current);
+        let geometry_at = |current: EffectTime| {
+            let mut geometry = effects.iter().fold(component.component().geometry.clone(), |geometry, effect| {
+                effect.effect_on_geometry(geometry, current)
+            });
+            geometry.scale = (geometry.scale.0 / proxy_scale, geometry.scale.1 / proxy_scale);
+
+            // the cropped frame stays where it was in the whole frame
+            geometry.coordinate.0 += (offset.0 as f64 * geometry.scale.0).round() as i32;
+            geometry.coordinate.1 += (offset.1 as f64 * geometry.scale.1).round() as i32;
+            geometry
+        };
+        let geometry = geometry_at(current);
+        let blend_mode = component.component().blend_mode.unwrap_or(layer.blend_mode);
+
+        // motion blur samples only the transform, the content is of the position
+        let motion_blur = component.component().motion_blur.as_ref().unwrap_or(&self.project().motion_blur);
+        let geometries = motion_blur.sample_positions(position, self.project().fps).into_iter().map(|time| {
+            geometry_at(component.component().effect_time(component.component().local_time(time)))
+        }).collect::<Vec<Geometry>>();
+
+        if geometries.iter().any(|sample| sample != &geometry) {
+            let canvas_size = options.canvas_size(size);
+            let ratio = canvas_size.0 as f64 / size.0 as f64;
+
+            return Some(ComponentFrame {
+                pixbuf: average_geometries(canvas_size, &pixbuf, &geometries, ratio, options.quality.interp_type()),
+                geometry: canvas_geometry(ratio),
+                blend_mode: blend_mode,
+            });
+        }
+
+        Some(ComponentFrame {
+            pixbuf: pixbuf,
+            geometry: geometry,
+            blend_mode: blend_mode,
+        })
+    }
+
+    // both clips blended by the transition, as a frame which covers the canvas
+    fn render_clip_transition(&self, size: (i32, i32), transition: &ClipTransition, layer: &Layer, progress: f32, position: gst::ClockTime, options: &RenderOptions) -> ComponentFrame {
+        let canvas_size = options.canvas_size(size);
+        let ratio = canvas_size.0 as f64 / size.0 as f64;
+        let interp_type = options.quality.interp_type();
+
+        let render = |component_id: &str| {
+            let canvas = new_canvas(canvas_size, true);
+            canvas.fill(0);
+            composite_frames(&canvas, self.render_component(size, component_id, layer, position, options).into_iter().collect(), ratio, interp_type);
+            canvas
+        };
+
+        ComponentFrame {
+            pixbuf: transition.kind.blend(&render(&transition.from), &render(&transition.to), progress),
+            geometry: canvas_geometry(ratio),
+            blend_mode: layer.blend_mode,
+        }
+    }
+
+    fn render_layer(&self, size: (i32, i32), layer: &Layer, active: &HashSet<&str>, position: gst::ClockTime, options: &RenderOptions) -> Vec<ComponentFrame> {
+        let mut in_transition = HashSet::new();
+        let mut frames = vec![];
+
+        for transition in &layer.transitions {
+            let cut = self.component_repo().get(&transition.from).component().end_time();
+            if let Some(progress) = transition.progress(cut, position) {
+                in_transition.insert(transition.from.as_str());
+                in_transition.insert(transition.to.as_str());
+                frames.push(self.render_clip_transition(size, transition, layer, progress, position, options));
+            }
+        }
+
+        frames.extend(layer.list().iter().filter(|component_id| {
+            active.contains(component_id.as_str()) && !in_transition.contains(component_id.as_str())
+        }).flat_map(|component_id| {
+            self.render_component(size, component_id, layer, position, options)
+        }));
+
+        frames
+    }
+
+    fn render_layers(&self, size: (i32, i32), layers: &[&Layer], position: gst::ClockTime, options: &RenderOptions) -> Vec<Vec<ComponentFrame>> {
+        let active = self.component_repo().active_at(position).into_iter().collect::<HashSet<&str>>();
+        layers.iter().map(|layer| self.render_layer(size, layer, &active, position, options)).collect()
+    }
+
+    fn compose_pixbuf(&self, position: gst::ClockTime, options: &RenderOptions) -> gdk_pixbuf::Pixbuf {
+        let project = self.project();
+        self.compose_layers(project.visible_layers(), project.size, &project.background, project.output_lut.as_ref(), position, options)
+    }
+
+    // layers of the project or one of its compositions, composed at the position
+    fn compose_layers(&self, layers: Vec<&Layer>, size: (i32, i32), background: &Background, output_lut: Option<&OutputLut>, position: gst::ClockTime, options: &RenderOptions) -> gdk_pixbuf::Pixbuf {
+        let canvas_size = options.canvas_size(size);
+        let ratio = canvas_size.0 as f64 / size.0 as f64;
+        let interp_type = options.quality.interp_type();
+
+        let pixbuf = new_canvas(canvas_size, background.has_alpha());
+        pixbuf.fill(background.pixel());
+
+        let mut rendered = self.render_layers(size, &layers, position, options).into_iter().map(Some).collect::<Vec<_>>();
+        let active = self.component_repo().active_at(position).into_iter().collect::<HashSet<&str>>();
+
+        for index in (0..layers.len()).rev() {
+            // a matte layer is drawn only through the layer below
+            if layers[index].track_matte.is_some() {
+                continue;
+            }
+
+            self.apply_adjustments(&pixbuf, layers[index], &active, position, interp_type);
+
+            let frames = rendered[index].take().unwrap();
+            let matte = if index > 0 { layers[index - 1].track_matte } else { None };
+
+            match matte {
+                Some(matte) => {
+                    // blend modes of the matted layer apply within the layer
+                    let target = new_canvas(canvas_size, true);
+                    target.fill(0);
+                    composite_frames(&target, frames, ratio, interp_type);
+
+                    let matte_canvas = new_canvas(canvas_size, true);
+                    matte_canvas.fill(0);
+                    composite_frames(&matte_canvas, rendered[index - 1].take().unwrap(), ratio, interp_type);
+
+                    apply_track_matte(&target, &matte_canvas, matte);
+                    composite_geometry(&pixbuf, &target, &Geometry::default(), interp_type);
+                },
+                None => composite_frames(&pixbuf, frames, ratio, interp_type),
+            }
+        }
+
+        let pixbuf = match output_lut {
+            Some(output) => match self.project().lut(&output.name) {
+                Some(lut) => lut.apply_pixbuf(pixbuf, output.intensity),
+                None => pixbuf,
+            },
+            None => pixbuf,
+        };
+
+        if canvas_size != size {
+            pixbuf.scale_simple(size.0, size.1, interp_type).unwrap()
+        } else {
+            pixbuf
+        }
+    }
+
+    // save frames as png files at the frame rate of the project, which keep the transparency of the background
+    fn save_frames(&self, directory: &str, frames: i32) -> Result<(), String> {
+        let delta = (1000 / self.project().fps) as u64;
+
+        for current in 0..frames {
+            let pixbuf = self.get_pixbuf_with(current as u64 * delta * gst::MSECOND, &self.project().export);
+            let buffer = pixbuf.save_to_bufferv("png", &[]).map_err(|t| t.to_string())?;
+            let path = Path::new(directory).join(format!("{:06}.png", current));
+            fs::write(path, buffer).map_err(|t| t.to_string())?;
+        }
+
+        Ok(())
+    }
+
     fn get_audio_streams(&self) -> Vec<(gst::ClockTime, Vec<gst::Element>)> {
-        self.component_repo().list().iter().flat_map(|item| {
-            if item.entity.get_audio_elements().len() == 0 {
+        self.project().audible_layers().into_iter().flat_map(|layer| {
+            layer.list().iter().map(move |component_id| (layer, component_id))
+        }).flat_map(|(layer, component_id)| {
+            let component = self.component_repo().get(component_id);
+            let mut elements = component.get_audio_elements();
+
+            if elements.len() == 0 {
                 None
             } else {
-                Some((item.entity.component().start_time, item.entity.get_audio_elements()))
+                for transition in layer.transitions.iter().filter(|t| &t.from == component_id || &t.to == component_id) {
+                    elements.push(self.crossfade_element(transition, component.component().start_time, &transition.to == component_id));
+                }
+
+                Some((component.component().start_time, elements))
             }
         }).collect()
     }
+
+    // a volume element following the transition, driven by the time of buffers
+    fn crossfade_element(&self, transition: &ClipTransition, start_time: gst::ClockTime, incoming: bool) -> gst::Element {
+        let cut = self.component_repo().get(&transition.from).component().end_time();
+        let volume = gst::ElementFactory::make("volume", None).unwrap();
+        let volume_ = volume.clone();
+        let transition = transition.clone();
+
+        volume.get_static_pad("sink").unwrap().add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                let _ = volume_.set_property("volume", &transition.gain(cut, start_time + buffer.get_pts(), incoming));
+            }
+
+            gst::PadProbeReturn::Ok
+        });
+
+        volume
+    }
+}
+
+fn new_canvas(size: (i32, i32), has_alpha: bool) -> gdk_pixbuf::Pixbuf {
+    gdk_pixbuf::Pixbuf::new(
+        gdk_pixbuf::Colorspace::Rgb,
+        has_alpha,
+        8,
+        size.0,
+        size.1
+    )
+}
+
+// geometry of a frame already on the canvas, which is scaled by ratio when composited
+fn canvas_geometry(ratio: f64) -> Geometry {
+    Geometry {
+        coordinate: (0, 0),
+        scale: (1.0 / ratio, 1.0 / ratio),
+        alpha: 255,
+    }
+}
+
+// average of src placed at each geometry on the canvas scaled by ratio, in premultiplied alpha
+fn average_geometries(size: (i32, i32), src: &gdk_pixbuf::Pixbuf, geometries: &[Geometry], ratio: f64, interp_type: gdk_pixbuf::InterpType) -> gdk_pixbuf::Pixbuf {
+    let canvas = new_canvas(size, true);
+    let rowstride = canvas.get_rowstride() as usize;
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let mut sum = vec![0.0f32; width * height * 4];
+
+    for geometry in geometries {
+        canvas.fill(0);
+        composite_geometry(&canvas, src, &geometry.scaled(ratio), interp_type);
+
+        let pixels = unsafe { canvas.get_pixels() };
+        for iy in 0..height {
+            for ix in 0..width {
+                let pos = iy * rowstride + ix * 4;
+                let index = (iy * width + ix) * 4;
+                let alpha = pixels[pos + 3] as f32;
+
+                for c in 0..3 {
+                    sum[index + c] += pixels[pos + c] as f32 * alpha;
+                }
+                sum[index + 3] += alpha;
+            }
+        }
+    }
+
+    let pixels = unsafe { canvas.get_pixels() };
+    for iy in 0..height {
+        for ix in 0..width {
+            let pos = iy * rowstride + ix * 4;
+            let index = (iy * width + ix) * 4;
+            let alpha = sum[index + 3];
+
+            for c in 0..3 {
+                pixels[pos + c] = if alpha > 0.0 { (sum[index + c] / alpha).round().min(255.0) as u8 } else { 0 };
+            }
+            pixels[pos + 3] = (alpha / geometries.len() as f32).round().min(255.0) as u8;
+        }
+    }
+
+    canvas
+}
+
+// composite frames in order, on the canvas scaled by ratio
+fn composite_frames(dest: &gdk_pixbuf::Pixbuf, frames: Vec<ComponentFrame>, ratio: f64, interp_type: gdk_pixbuf::InterpType) {
+    for frame in frames {
+        composite_blend(dest, &frame.pixbuf, &frame.geometry.scaled(ratio), frame.blend_mode, interp_type);
+    }
+}
+
+// multiply the alpha of target by the opacity given by matte, both of the same size with alpha
+fn apply_track_matte(target: &gdk_pixbuf::Pixbuf, matte: &gdk_pixbuf::Pixbuf, track_matte: TrackMatte) {
+    let target_rowstride = target.get_rowstride() as usize;
+    let matte_rowstride = matte.get_rowstride() as usize;
+    let target_pixels = unsafe { target.get_pixels() };
+    let matte_pixels = unsafe { matte.get_pixels() };
+
+    for iy in 0..target.get_height() as usize {
+        for ix in 0..target.get_width() as usize {
+            let t = iy * target_rowstride + ix * 4;
+            let m = iy * matte_rowstride + ix * 4;
+            let opacity = track_matte.opacity(matte_pixels[m], matte_pixels[m + 1], matte_pixels[m + 2], matte_pixels[m + 3]);
+
+            target_pixels[t + 3] = (target_pixels[t + 3] as f32 * opacity).round() as u8;
+        }
+    }
+}
+
+// the region of dest covered by src, as (left, top, right, bottom)
+fn clip_region(dest: &gdk_pixbuf::Pixbuf, src: &gdk_pixbuf::Pixbuf, geometry: &Geometry) -> Option<(i32, i32, i32, i32)> {
+    let (x, y) = geometry.coordinate;
+    let (scale_x, scale_y) = geometry.scale;
+    if scale_x <= 0.0 || scale_y <= 0.0 || geometry.alpha <= 0 {
+        return None;
+    }
+
+    let width = (src.get_width() as f64 * scale_x).round() as i32;
+    let height = (src.get_height() as f64 * scale_y).round() as i32;
+
+    let left = cmp::max(x, 0);
+    let top = cmp::max(y, 0);
+    let right = cmp::min(x + width, dest.get_width());
+    let bottom = cmp::min(y + height, dest.get_height());
+    if right <= left || bottom <= top {
+        return None;
+    }
+
+    Some((left, top, right, bottom))
+}
+
+// composite src onto dest, clipping the region which lies outside of dest
+fn composite_geometry(dest: &gdk_pixbuf::Pixbuf, src: &gdk_pixbuf::Pixbuf, geometry: &Geometry, interp_type: gdk_pixbuf::InterpType) {
+    if let Some((left, top, right, bottom)) = clip_region(dest, src, geometry) {
+        src.composite(
+            dest, left, top,
+            right - left, bottom - top,
+            geometry.coordinate.0.into(), geometry.coordinate.1.into(),
+            geometry.scale.0, geometry.scale.1,
+            interp_type, cmp::min(geometry.alpha, 255));
+    }
 }
 
+fn composite_blend(dest: &gdk_pixbuf::Pixbuf, src: &gdk_pixbuf::Pixbuf, geometry: &Geometry, blend_mode: BlendMode, interp_type: gdk_pixbuf::InterpType) {
+    if blend_mode == BlendMode::Normal {
+        return composite_geometry(dest, src, geometry, interp_type);
+    }
+
+    // place src on a transparent buffer first, then blend it by hand
+    if let Some((left, top, right, bottom)) = clip_region(dest, src, geometry) {
+        let placed = gdk_pixbuf::Pixbuf::new(
+            gdk_pixbuf::Colorspace::Rgb,
+            true,
+            8,
+            right - left,
+            bottom - top,
+        );
+        placed.fill(0);
+
+        src.composite(
+            &placed, 0, 0,
+            right - left, bottom - top,
+            (geometry.coordinate.0 - left).into(), (geometry.coordinate.1 - top).into(),
+            geometry.scale.0, geometry.scale.1,
+            interp_type, cmp::min(geometry.alpha, 255));
+
+        blend_pixbuf(dest, &placed, (left, top), blend_mode);
+    }
+}
+
+// blend src (with alpha) onto dest at the given position
+// following the separable blend modes of W3C Compositing and Blending
+fn blend_pixbuf(dest: &gdk_pixbuf::Pixbuf, src: &gdk_pixbuf::Pixbuf, position: (i32, i32), blend_mode: BlendMode) {
+    let dest_channels = dest.get_n_channels() as usize;
+    let dest_rowstride = dest.get_rowstride() as usize;
+    let dest_has_alpha = dest.get_has_alpha();
+    let src_rowstride = src.get_rowstride() as usize;
+    let dest_pixels = unsafe { dest.get_pixels() };
+    let src_pixels = unsafe { src.get_pixels() };
+
+    let width = cmp::min(src.get_width(), dest.get_width() - position.0);
+    let height = cmp::min(src.get_height(), dest.get_height() - position.1);
+
+    for iy in 0..cmp::max(height, 0) as usize {
+        for ix in 0..cmp::max(width, 0) as usize {
+            let s = iy * src_rowstride + ix * 4;
+            let d = (iy + position.1 as usize) * dest_rowstride + (ix + position.0 as usize) * dest_channels;
+
+            let alpha_s = src_pixels[s + 3] as f32 / 255.0;
+            if alpha_s == 0.0 {
+                continue;
+            }
+
+            let alpha_b = if dest_has_alpha { dest_pixels[d + 3] as f32 / 255.0 } else { 1.0 };
+            let alpha_o = alpha_s + alpha_b * (1.0 - alpha_s);
+
+            for c in 0..3 {
+                let cs = src_pixels[s + c] as f32 / 255.0;
+                let cb = dest_pixels[d + c] as f32 / 255.0;
+                let mixed = (1.0 - alpha_b) * cs + alpha_b * blend_mode.blend(cb, cs);
+                let co = (alpha_s * mixed + alpha_b * cb * (1.0 - alpha_s)) / alpha_o;
+
+                dest_pixels[d + c] = (co.max(0.0).min(1.0) * 255.0).round() as u8;
+            }
+
+            if dest_has_alpha {
+                dest_pixels[d + 3] = (alpha_o * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 1x1 pixbuf of 0xRRGGBBAA
+    fn pixel(rgba: u32) -> gdk_pixbuf::Pixbuf {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, true, 8, 1, 1);
+        pixbuf.fill(rgba);
+        pixbuf
+    }
+
+    fn rgba(pixbuf: &gdk_pixbuf::Pixbuf) -> Vec<u8> {
+        unsafe { pixbuf.get_pixels()[0..4].to_vec() }
+    }
+
+    fn blended(backdrop: u32, source: u32, blend_mode: BlendMode) -> Vec<u8> {
+        let dest = pixel(backdrop);
+        blend_pixbuf(&dest, &pixel(source), (0, 0), blend_mode);
+        rgba(&dest)
+    }
+
+    #[test]
+    fn blend_opaque_pixels() {
+        let (backdrop, source) = (0xc86432ff, 0x64c8faff);
+
+        assert_eq!(blended(backdrop, source, BlendMode::Normal), vec![100, 200, 250, 255]);
+        assert_eq!(blended(backdrop, source, BlendMode::Multiply), vec![78, 78, 49, 255]);
+        assert_eq!(blended(backdrop, source, BlendMode::Screen), vec![222, 222, 251, 255]);
+        assert_eq!(blended(backdrop, source, BlendMode::Add), vec![255, 255, 255, 255]);
+        assert_eq!(blended(backdrop, source, BlendMode::Overlay), vec![188, 157, 98, 255]);
+        assert_eq!(blended(backdrop, source, BlendMode::Darken), vec![100, 100, 50, 255]);
+        assert_eq!(blended(backdrop, source, BlendMode::Lighten), vec![200, 200, 250, 255]);
+        assert_eq!(blended(backdrop, source, BlendMode::Difference), vec![100, 100, 200, 255]);
+    }
+
+    #[test]
+    fn blend_translucent_pixels() {
+        // half of the source over the backdrop, and the source over half of the backdrop
+        assert_eq!(blended(0xc86432ff, 0x64c8fa80, BlendMode::Multiply), vec![139, 89, 50, 255]);
+        assert_eq!(blended(0xc86432ff, 0x64c8fa80, BlendMode::Difference), vec![150, 100, 125, 255]);
+        assert_eq!(blended(0xc8643280, 0x64c8faff, BlendMode::Multiply), vec![89, 139, 149, 255]);
+
+        // the source as it is on a transparent backdrop, and nothing from a transparent source
+        assert_eq!(blended(0xc8643200, 0x64c8faff, BlendMode::Overlay), vec![100, 200, 250, 255]);
+        assert_eq!(blended(0xc86432ff, 0x64c8fa00, BlendMode::Screen), vec![200, 100, 50, 255]);
+    }
+
+    #[test]
+    fn track_matte_multiplies_alpha() {
+        let matted = |matte: u32, track_matte: TrackMatte| {
+            let target = pixel(0x64c8faff);
+            apply_track_matte(&target, &pixel(matte), track_matte);
+            rgba(&target)
+        };
+
+        assert_eq!(matted(0x00000080, TrackMatte::Alpha), vec![100, 200, 250, 128]);
+        assert_eq!(matted(0x00000080, TrackMatte::AlphaInverted), vec![100, 200, 250, 127]);
+        assert_eq!(matted(0xffffffff, TrackMatte::Luma), vec![100, 200, 250, 255]);
+        assert_eq!(matted(0xffffffff, TrackMatte::LumaInverted), vec![100, 200, 250, 0]);
+    }
+
+    #[test]
+    fn composite_blend_with_geometry() {
+        let geometry = Geometry {
+            coordinate: (0, 0),
+            scale: (1.0, 1.0),
+            alpha: 255,
+        };
+
+        for &(blend_mode, expected) in &[(BlendMode::Normal, [100, 200, 250, 255]), (BlendMode::Difference, [100, 100, 200, 255])] {
+            let dest = pixel(0xc86432ff);
+            composite_blend(&dest, &pixel(0x64c8faff), &geometry, blend_mode, gdk_pixbuf::InterpType::Nearest);
+            assert_eq!(rgba(&dest), expected.to_vec());
+        }
+
+        // outside of the canvas
+        let dest = pixel(0xc86432ff);
+        composite_blend(&dest, &pixel(0x64c8faff), &Geometry { coordinate: (1, 0), ..geometry }, BlendMode::Multiply, gdk_pixbuf::InterpType::Nearest);
+        assert_eq!(rgba(&dest), vec![200, 100, 50, 255]);
+    }
+
+    // a gray canvas under an adjustment component from 0 to 1000ms, which brightens by 0.2
+    fn adjusted_project(alpha: i32) -> ::Madder {
+        let mut madder = ::Madder::new();
+        madder.from_yaml_string(&format!(r#"
+version: 1
+project:
+  layers:
+    - components: [adjustment]
+  size: [2, 2]
+  length: 2000
+  position: 0
+  background:
+    Color: {{ red: 100, green: 100, blue: 100 }}
+components:
+  - id: adjustment
+    component_type: Adjustment
+    start_time: 0
+    length: 1000
+    effect: [brighten]
+    geometry:
+      alpha: {}
+effects:
+  - id: brighten
+    effect_type: brightness
+    transition: Linear
+    start_value: 0.2
+    end_value: 0.2
+    intervals: []
+"#, alpha)).unwrap();
+
+        madder
+    }
+
+    fn first_pixel(madder: &::Madder, position: gst::ClockTime) -> Vec<u8> {
+        let pixbuf = madder.get_pixbuf_with(position, &RenderOptions::export());
+        unsafe { pixbuf.get_pixels()[0..3].to_vec() }
+    }
+
+    #[test]
+    fn adjustments_apply_to_layers_below() {
+        let madder = adjusted_project(255);
+        assert_eq!(first_pixel(&madder, 500 * gst::MSECOND), vec![151, 151, 151]);
+
+        // not active after its end
+        assert_eq!(first_pixel(&madder, 1500 * gst::MSECOND), vec![100, 100, 100]);
+    }
+
+    #[test]
+    fn opacity_of_adjustments() {
+        let half = first_pixel(&adjusted_project(128), 500 * gst::MSECOND);
+        assert!(half[0] > 100 && half[0] < 151, "{:?}", half);
+
+        assert_eq!(first_pixel(&adjusted_project(0), 500 * gst::MSECOND), vec![100, 100, 100]);
+    }
+
+    #[test]
+    fn references_at_the_time_of_their_components() {
+        let mut madder = ::Madder::new();
+        madder.from_yaml_string(r#"
+version: 1
+project:
+  layers:
+    - components: [referring, referred]
+  size: [640, 480]
+  length: 2000
+  position: 0
+components:
+  - id: referring
+    component_type: Adjustment
+    start_time: 0
+    length: 1000
+    effect: [copy]
+  - id: referred
+    component_type: Adjustment
+    start_time: 500
+    length: 1000
+    effect: [fade]
+effects:
+  - id: copy
+    effect_type: alpha
+    transition: Linear
+    start_value: 0
+    end_value: 0
+    intervals: []
+    expression: effect("fade")
+  - id: fade
+    effect_type: alpha
+    transition: Linear
+    start_value: 0
+    end_value: 100
+    intervals: []
+"#).unwrap();
+
+        // 200ms in the referred component, while 700ms in the referring one
+        let component = madder.component_repo().get("referring").component();
+        let position = 700 * gst::MSECOND;
+        let effects = madder.resolve_effects(component, position);
+        let current = component.effect_time(component.local_time(position));
+
+        assert_eq!(effects[0].scalar(current), 20.0);
+    }
+}
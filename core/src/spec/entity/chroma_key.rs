@@ -0,0 +1,127 @@
+extern crate gdk_pixbuf;
+use util::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChromaKey {
+    pub key_color: (u8, u8, u8),
+
+    // pixels closer to the key color than tolerance become transparent,
+    // where the distance is taken in the CbCr plane, e.g. gray is about 0.75 away from pure green
+    pub tolerance: f32,
+
+    // width of the range, beyond tolerance, where alpha rises linearly
+    pub softness: f32,
+
+    // how much the color of the key is removed from the remaining pixels, in [0,1]
+    pub spill: f32,
+}
+
+// the chroma part of YCbCr (BT.601), scaled by sqrt(2)
+fn chroma(r: f32, g: f32, b: f32) -> (f32, f32) {
+    const NORMALIZE: f32 = 1.4142;
+
+    ((-0.168736 * r - 0.331264 * g + 0.5 * b) * NORMALIZE,
+     (0.5 * r - 0.418688 * g - 0.081312 * b) * NORMALIZE)
+}
+
+impl ChromaKey {
+    fn opacity(&self, distance: f32) -> f32 {
+        if distance <= self.tolerance {
+            0.0
+        } else if self.softness <= 0.0 || distance >= self.tolerance + self.softness {
+            1.0
+        } else {
+            (distance - self.tolerance) / self.softness
+        }
+    }
+
+    // a keyed copy of pixbuf, which is always RGBA
+    pub fn apply_pixbuf(&self, pixbuf: gdk_pixbuf::Pixbuf) -> gdk_pixbuf::Pixbuf {
+        let to_f32 = |c: u8| c as f32 / 255.0;
+
+        let pixbuf = writable_copy(&pixbuf, true);
+        let rowstride = pixbuf.get_rowstride() as usize;
+        let pixels = unsafe { pixbuf.get_pixels() };
+
+        let key = (to_f32(self.key_color.0), to_f32(self.key_color.1), to_f32(self.key_color.2));
+        let key_chroma = chroma(key.0, key.1, key.2);
+
+        // the dominant channel of the key color is suppressed for spill
+        let dominant = if key.1 >= key.0 && key.1 >= key.2 { 1 } else if key.2 >= key.0 { 2 } else { 0 };
+
+        for iy in 0..pixbuf.get_height() as usize {
+            for ix in 0..pixbuf.get_width() as usize {
+                let pos = iy * rowstride + ix * 4;
+                let (cb, cr) = chroma(to_f32(pixels[pos]), to_f32(pixels[pos + 1]), to_f32(pixels[pos + 2]));
+                let distance = ((cb - key_chroma.0).powi(2) + (cr - key_chroma.1).powi(2)).sqrt();
+
+                pixels[pos + 3] = (pixels[pos + 3] as f32 * self.opacity(distance)).round() as u8;
+
+                if self.spill > 0.0 {
+                    let others = (0..3).filter(|&c| c != dominant).map(|c| pixels[pos + c] as f32).sum::<f32>() / 2.0;
+                    let value = pixels[pos + dominant] as f32;
+                    if value > others {
+                        pixels[pos + dominant] = (value - self.spill.min(1.0) * (value - others)).round() as u8;
+                    }
+                }
+            }
+        }
+
+        pixbuf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyed(key: &ChromaKey, rgb: (u8, u8, u8)) -> Vec<u8> {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, false, 8, 1, 1);
+        pixbuf.put_pixel(0, 0, rgb.0, rgb.1, rgb.2, 255);
+
+        let keyed = key.apply_pixbuf(pixbuf);
+        assert!(keyed.get_has_alpha());
+        unsafe { keyed.get_pixels()[0..4].to_vec() }
+    }
+
+    fn green(spill: f32) -> ChromaKey {
+        ChromaKey {
+            key_color: (0, 255, 0),
+            tolerance: 0.1,
+            softness: 0.2,
+            spill: spill,
+        }
+    }
+
+    #[test]
+    fn opacity_by_distance() {
+        let key = green(0.0);
+        assert_eq!(key.opacity(0.1), 0.0);
+        assert!((key.opacity(0.2) - 0.5).abs() < 1e-6);
+        assert_eq!(key.opacity(0.3), 1.0);
+
+        let hard = ChromaKey { softness: 0.0, ..key };
+        assert_eq!((hard.opacity(0.1), hard.opacity(0.11)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn key_pixels() {
+        let key = green(0.0);
+
+        assert_eq!(keyed(&key, (0, 255, 0)), vec![0, 255, 0, 0]);
+
+        // about 0.19 away from the key, within the softness
+        assert_eq!(keyed(&key, (40, 230, 40)), vec![40, 230, 40, 118]);
+        assert_eq!(keyed(&key, (128, 128, 128)), vec![128, 128, 128, 255]);
+        assert_eq!(keyed(&key, (255, 0, 255)), vec![255, 0, 255, 255]);
+    }
+
+    #[test]
+    fn suppress_spill() {
+        assert_eq!(keyed(&green(1.0), (100, 200, 100)), vec![100, 100, 100, 255]);
+        assert_eq!(keyed(&green(0.5), (100, 200, 100)), vec![100, 150, 100, 255]);
+
+        // the key color is not dominant
+        assert_eq!(keyed(&green(1.0), (200, 100, 100)), vec![200, 100, 100, 255]);
+    }
+}
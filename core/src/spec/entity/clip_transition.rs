@@ -0,0 +1,306 @@
+extern crate gdk_pixbuf;
+extern crate gstreamer as gst;
+use std::cmp;
+use util::*;
+use spec::*;
+
+// the direction in which the incoming clip moves
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn unit(&self) -> (f32, f32) {
+        use Direction::*;
+
+        match self {
+            &Left => (-1.0, 0.0),
+            &Right => (1.0, 0.0),
+            &Up => (0.0, -1.0),
+            &Down => (0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TransitionKind {
+    Crossfade,
+    DipToColor {
+        red: u8,
+        green: u8,
+        blue: u8,
+    },
+    Wipe {
+        direction: Direction,
+    },
+    Slide {
+        direction: Direction,
+    },
+    Push {
+        direction: Direction,
+    },
+}
+
+// dissolve from a to b by t, in premultiplied alpha
+fn dissolve(a: &gdk_pixbuf::Pixbuf, b: &gdk_pixbuf::Pixbuf, t: f32) -> gdk_pixbuf::Pixbuf {
+    let result = a.copy().unwrap();
+    let rowstride = result.get_rowstride() as usize;
+    let b_rowstride = b.get_rowstride() as usize;
+    let pixels = unsafe { result.get_pixels() };
+    let b_pixels = unsafe { b.get_pixels() };
+
+    for iy in 0..result.get_height() as usize {
+        for ix in 0..result.get_width() as usize {
+            let pos = iy * rowstride + ix * 4;
+            let b_pos = iy * b_rowstride + ix * 4;
+            let alpha_a = pixels[pos + 3] as f32 / 255.0 * (1.0 - t);
+            let alpha_b = b_pixels[b_pos + 3] as f32 / 255.0 * t;
+            let alpha = alpha_a + alpha_b;
+
+            for c in 0..3 {
+                let value = if alpha > 0.0 { (pixels[pos + c] as f32 * alpha_a + b_pixels[b_pos + c] as f32 * alpha_b) / alpha } else { 0.0 };
+                pixels[pos + c] = value.round() as u8;
+            }
+            pixels[pos + 3] = (alpha * 255.0).round() as u8;
+        }
+    }
+
+    result
+}
+
+// composite src onto dest at the offset, clipping the outside of dest
+fn place(dest: &gdk_pixbuf::Pixbuf, src: &gdk_pixbuf::Pixbuf, offset: (i32, i32)) {
+    let left = cmp::max(offset.0, 0);
+    let top = cmp::max(offset.1, 0);
+    let right = cmp::min(offset.0 + src.get_width(), dest.get_width());
+    let bottom = cmp::min(offset.1 + src.get_height(), dest.get_height());
+
+    if left < right && top < bottom {
+        src.composite(
+            dest, left, top,
+            right - left, bottom - top,
+            offset.0.into(), offset.1.into(),
+            1.0, 1.0,
+            gdk_pixbuf::InterpType::Nearest, 255);
+    }
+}
+
+impl TransitionKind {
+    // a frame between a (outgoing) and b (incoming), RGBA of the same size, at progress in [0,1]
+    pub fn blend(&self, a: &gdk_pixbuf::Pixbuf, b: &gdk_pixbuf::Pixbuf, progress: f32) -> gdk_pixbuf::Pixbuf {
+        use TransitionKind::*;
+
+        let width = a.get_width();
+        let height = a.get_height();
+        let shift = |unit: (f32, f32), t: f32| ((unit.0 * t * width as f32).round() as i32, (unit.1 * t * height as f32).round() as i32);
+
+        match self {
+            &Crossfade => dissolve(a, b, progress),
+            &DipToColor { red, green, blue } => {
+                let color = a.copy().unwrap();
+                color.fill((red as u32) << 24 | (green as u32) << 16 | (blue as u32) << 8 | 0xff);
+
+                if progress < 0.5 {
+                    dissolve(a, &color, progress * 2.0)
+                } else {
+                    dissolve(&color, b, progress * 2.0 - 1.0)
+                }
+            },
+            &Wipe { direction } => {
+                // the edge moves in the direction, revealing b behind it
+                let result = a.copy().unwrap();
+                let (w, h) = ((width as f32 * progress).round() as i32, (height as f32 * progress).round() as i32);
+                let (x, y, w, h) = match direction {
+                    Direction::Left => (width - w, 0, w, height),
+                    Direction::Right => (0, 0, w, height),
+                    Direction::Up => (0, height - h, width, h),
+                    Direction::Down => (0, 0, width, h),
+                };
+                if w > 0 && h > 0 {
+                    b.copy_area(x, y, w, h, &result, x, y);
+                }
+
+                result
+            },
+            &Slide { direction } => {
+                let result = a.copy().unwrap();
+                let unit = direction.unit();
+                place(&result, b, shift((-unit.0, -unit.1), 1.0 - progress));
+
+                result
+            },
+            &Push { direction } => {
+                let result = a.copy().unwrap();
+                result.fill(0);
+                let unit = direction.unit();
+                place(&result, a, shift(unit, progress));
+                place(&result, b, shift((-unit.0, -unit.1), 1.0 - progress));
+
+                result
+            },
+        }
+    }
+}
+
+fn easing_default() -> Transition { Transition::Linear }
+
+// A transition between two clips on the same layer, centered on the cut where `from` ends.
+// Outside of their own range, clips hold their first or last frame.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClipTransition {
+    pub from: String,
+    pub to: String,
+    pub kind: TransitionKind,
+
+    #[serde(serialize_with = "SerTime::serialize_time")]
+    #[serde(deserialize_with = "SerTime::deserialize_time")]
+    pub duration: gst::ClockTime,
+
+    #[serde(default = "easing_default")]
+    pub easing: Transition,
+}
+
+impl ClipTransition {
+    // linear progress in [0,1] at the position, or None outside of the transition
+    pub fn linear_progress(&self, cut: gst::ClockTime, position: gst::ClockTime) -> Option<f32> {
+        let cut = cut.nseconds()? as f64;
+        let duration = self.duration.nseconds()? as f64;
+        let position = position.nseconds()? as f64;
+        let start = cut - duration / 2.0;
+
+        if duration <= 0.0 || position < start || position > start + duration {
+            None
+        } else {
+            Some(((position - start) / duration) as f32)
+        }
+    }
+
+    // overshooting easings are clamped, since frames and volumes can not go beyond both clips
+    pub fn progress(&self, cut: gst::ClockTime, position: gst::ClockTime) -> Option<f32> {
+        self.linear_progress(cut, position).map(|p| self.easing.get_in_interval(p).max(0.0).min(1.0))
+    }
+
+    // volume of the incoming (or outgoing) clip at the position, for audio crossfade
+    pub fn gain(&self, cut: gst::ClockTime, position: gst::ClockTime, incoming: bool) -> f64 {
+        let before = match (cut.nseconds(), position.nseconds()) {
+            (Some(c), Some(p)) => p < c,
+            _ => true,
+        };
+        let progress = self.progress(cut, position).unwrap_or(if before { 0.0 } else { 1.0 }) as f64;
+
+        if incoming { progress } else { 1.0 - progress }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(kind: TransitionKind, easing: Transition) -> ClipTransition {
+        ClipTransition {
+            from: "a".to_string(),
+            to: "b".to_string(),
+            kind: kind,
+            duration: 400 * gst::MSECOND,
+            easing: easing,
+        }
+    }
+
+    // a row where red is x and blue tells the clip
+    fn clip(width: i32, blue: u8) -> gdk_pixbuf::Pixbuf {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, true, 8, width, 1);
+        for x in 0..width {
+            pixbuf.put_pixel(x, 0, x as u8, 0, blue, 255);
+        }
+        pixbuf
+    }
+
+    // (x in the clip, blue) of each pixel
+    fn sources(pixbuf: &gdk_pixbuf::Pixbuf) -> Vec<(u8, u8)> {
+        let pixels = unsafe { pixbuf.get_pixels() };
+        (0..pixbuf.get_width() as usize).map(|x| (pixels[x * 4], pixels[x * 4 + 2])).collect()
+    }
+
+    #[test]
+    fn progress_around_the_cut() {
+        let t = transition(TransitionKind::Crossfade, Transition::Linear);
+        let cut = 1000 * gst::MSECOND;
+
+        assert_eq!(t.linear_progress(cut, 799 * gst::MSECOND), None);
+        assert_eq!(t.linear_progress(cut, 800 * gst::MSECOND), Some(0.0));
+        assert_eq!(t.linear_progress(cut, cut), Some(0.5));
+        assert_eq!(t.linear_progress(cut, 1200 * gst::MSECOND), Some(1.0));
+        assert_eq!(t.linear_progress(cut, 1201 * gst::MSECOND), None);
+
+        let instant = ClipTransition { duration: 0 * gst::MSECOND, ..t };
+        assert_eq!(instant.linear_progress(cut, cut), None);
+    }
+
+    #[test]
+    fn overshooting_progress_is_clamped() {
+        let t = transition(TransitionKind::Crossfade, Transition::CubicBezier { x1: 0.3, y1: 2.0, x2: 0.7, y2: -1.0 });
+        let cut = 1000 * gst::MSECOND;
+
+        for ms in 800..1201 {
+            let progress = t.progress(cut, ms * gst::MSECOND).unwrap();
+            assert!(progress >= 0.0 && progress <= 1.0, "{} at {}", progress, ms);
+        }
+    }
+
+    #[test]
+    fn gain_of_clips() {
+        let t = transition(TransitionKind::Crossfade, Transition::Linear);
+        let cut = 1000 * gst::MSECOND;
+
+        assert_eq!((t.gain(cut, 0 * gst::MSECOND, true), t.gain(cut, 0 * gst::MSECOND, false)), (0.0, 1.0));
+        assert_eq!((t.gain(cut, cut, true), t.gain(cut, cut, false)), (0.5, 0.5));
+        assert_eq!((t.gain(cut, 900 * gst::MSECOND, true), t.gain(cut, 900 * gst::MSECOND, false)), (0.25, 0.75));
+        assert_eq!((t.gain(cut, 2000 * gst::MSECOND, true), t.gain(cut, 2000 * gst::MSECOND, false)), (1.0, 0.0));
+    }
+
+    #[test]
+    fn wipe_covers_progress_of_width() {
+        let (a, b) = (clip(8, 0), clip(8, 255));
+        let wipe = |direction, progress| sources(&TransitionKind::Wipe { direction: direction }.blend(&a, &b, progress));
+        let covered = |pixels: Vec<(u8, u8)>| pixels.iter().map(|&(_, blue)| if blue == 255 { 'b' } else { 'a' }).collect::<String>();
+
+        assert_eq!(covered(wipe(Direction::Right, 0.0)), "aaaaaaaa");
+        assert_eq!(covered(wipe(Direction::Right, 0.25)), "bbaaaaaa");
+        assert_eq!(covered(wipe(Direction::Left, 0.25)), "aaaaaabb");
+        assert_eq!(covered(wipe(Direction::Right, 0.5)), "bbbbaaaa");
+        assert_eq!(covered(wipe(Direction::Right, 1.0)), "bbbbbbbb");
+
+        // pixels of b stay where they are
+        assert_eq!(wipe(Direction::Left, 0.5)[5], (5, 255));
+    }
+
+    #[test]
+    fn slide_and_push() {
+        let (a, b) = (clip(4, 0), clip(4, 255));
+
+        let slide = TransitionKind::Slide { direction: Direction::Right };
+        assert_eq!(sources(&slide.blend(&a, &b, 0.0)), sources(&a));
+        assert_eq!(sources(&slide.blend(&a, &b, 0.5)), vec![(2, 255), (3, 255), (2, 0), (3, 0)]);
+        assert_eq!(sources(&slide.blend(&a, &b, 1.0)), sources(&b));
+
+        // a is pushed out by b
+        let push = TransitionKind::Push { direction: Direction::Right };
+        assert_eq!(sources(&push.blend(&a, &b, 0.5)), vec![(2, 255), (3, 255), (0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn crossfade_and_dip() {
+        let (black, white) = (clip(1, 0), clip(1, 255));
+
+        assert_eq!(sources(&TransitionKind::Crossfade.blend(&black, &white, 0.5)), vec![(0, 128)]);
+
+        let dip = TransitionKind::DipToColor { red: 10, green: 20, blue: 30 };
+        let pixel = |progress| unsafe { dip.blend(&black, &white, progress).get_pixels()[0..4].to_vec() };
+        assert_eq!(pixel(0.5), vec![10, 20, 30, 255]);
+        assert_eq!(pixel(1.0), vec![0, 0, 255, 255]);
+    }
+}
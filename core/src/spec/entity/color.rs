@@ -0,0 +1,203 @@
+extern crate gdk_pixbuf;
+use std::f32::consts::PI;
+use util::*;
+
+// channels are in [0,1], and the neutral value of each correction leaves the image as it is
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorCorrection {
+    // offset added to each channel, neutral at 0
+    Brightness(f32),
+
+    // factor around the middle gray, neutral at 1
+    Contrast(f32),
+
+    // factor of the distance from the luma, neutral at 1
+    Saturation(f32),
+
+    // rotation of hue in degrees, neutral at 0
+    HueShift(f32),
+
+    // neutral at 1
+    Gamma(f32),
+
+    // in stops, neutral at 0
+    Exposure(f32),
+
+    Levels {
+        input_black: f32,
+        input_white: f32,
+        gamma: f32,
+        output_black: f32,
+        output_white: f32,
+    },
+
+    // mix with the color by amount, neutral at 0
+    Tint {
+        color: (f32, f32, f32),
+        amount: f32,
+    },
+}
+
+fn luma(r: f32, g: f32, b: f32) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+fn gamma(c: f32, gamma: f32) -> f32 {
+    if gamma <= 0.0 { c } else { c.max(0.0).powf(1.0 / gamma) }
+}
+
+impl ColorCorrection {
+    // corrections which map each channel independently
+    fn is_per_channel(&self) -> bool {
+        use ColorCorrection::*;
+
+        match self {
+            &Saturation(_) | &HueShift(_) | &Tint { .. } => false,
+            _ => true,
+        }
+    }
+
+    fn apply_channel(&self, c: f32) -> f32 {
+        use ColorCorrection::*;
+
+        match self {
+            &Brightness(v) => c + v,
+            &Contrast(v) => (c - 0.5) * v + 0.5,
+            &Gamma(v) => gamma(c, v),
+            &Exposure(v) => c * 2.0f32.powf(v),
+            &Levels { input_black, input_white, gamma: g, output_black, output_white } => {
+                let range = (input_white - input_black).max(1.0 / 255.0);
+                let c = ((c - input_black) / range).max(0.0).min(1.0);
+                output_black + gamma(c, g) * (output_white - output_black)
+            },
+            _ => c,
+        }
+    }
+
+    pub fn apply(&self, rgb: (f32, f32, f32)) -> (f32, f32, f32) {
+        use ColorCorrection::*;
+
+        let (r, g, b) = rgb;
+        match self {
+            &Saturation(v) => {
+                let l = luma(r, g, b);
+                (l + (r - l) * v, l + (g - l) * v, l + (b - l) * v)
+            },
+            &HueShift(v) => {
+                // the hue-rotate matrix of CSS filter effects
+                let (sin, cos) = (v * PI / 180.0).sin_cos();
+
+                ((0.213 + cos * 0.787 - sin * 0.213) * r + (0.715 - cos * 0.715 - sin * 0.715) * g + (0.072 - cos * 0.072 + sin * 0.928) * b,
+                 (0.213 - cos * 0.213 + sin * 0.143) * r + (0.715 + cos * 0.285 + sin * 0.140) * g + (0.072 - cos * 0.072 - sin * 0.283) * b,
+                 (0.213 - cos * 0.213 - sin * 0.787) * r + (0.715 - cos * 0.715 + sin * 0.715) * g + (0.072 + cos * 0.928 + sin * 0.072) * b)
+            },
+            &Tint { color, amount } => (r + (color.0 - r) * amount, g + (color.1 - g) * amount, b + (color.2 - b) * amount),
+            _ => (self.apply_channel(r), self.apply_channel(g), self.apply_channel(b)),
+        }
+    }
+
+    // a corrected copy of pixbuf, the alpha channel is kept as it is
+    pub fn apply_pixbuf(&self, pixbuf: gdk_pixbuf::Pixbuf) -> gdk_pixbuf::Pixbuf {
+        let to_u8 = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+        let to_f32 = |c: u8| c as f32 / 255.0;
+
+        let pixbuf = writable_copy(&pixbuf, false);
+        let channels = pixbuf.get_n_channels() as usize;
+        let rowstride = pixbuf.get_rowstride() as usize;
+        let pixels = unsafe { pixbuf.get_pixels() };
+
+        let table = if self.is_per_channel() {
+            Some((0..256).map(|c| to_u8(self.apply_channel(to_f32(c as u8)))).collect::<Vec<u8>>())
+        } else {
+            None
+        };
+
+        for iy in 0..pixbuf.get_height() as usize {
+            for ix in 0..pixbuf.get_width() as usize {
+                let pos = iy * rowstride + ix * channels;
+
+                match table {
+                    Some(ref table) => {
+                        for c in 0..3 {
+                            pixels[pos + c] = table[pixels[pos + c] as usize];
+                        }
+                    },
+                    None => {
+                        let (r, g, b) = self.apply((to_f32(pixels[pos]), to_f32(pixels[pos + 1]), to_f32(pixels[pos + 2])));
+                        pixels[pos] = to_u8(r);
+                        pixels[pos + 1] = to_u8(g);
+                        pixels[pos + 2] = to_u8(b);
+                    },
+                }
+            }
+        }
+
+        pixbuf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rgb(actual: (f32, f32, f32), expected: (f32, f32, f32)) {
+        for &(a, e) in &[(actual.0, expected.0), (actual.1, expected.1), (actual.2, expected.2)] {
+            assert!((a - e).abs() < 1e-3, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn neutral_corrections() {
+        let rgb = (0.2, 0.5, 0.9);
+        for correction in vec![
+            ColorCorrection::Brightness(0.0),
+            ColorCorrection::Contrast(1.0),
+            ColorCorrection::Saturation(1.0),
+            ColorCorrection::HueShift(0.0),
+            ColorCorrection::Gamma(1.0),
+            ColorCorrection::Exposure(0.0),
+            ColorCorrection::Levels { input_black: 0.0, input_white: 1.0, gamma: 1.0, output_black: 0.0, output_white: 1.0 },
+            ColorCorrection::Tint { color: (1.0, 0.0, 0.0), amount: 0.0 },
+        ] {
+            assert_rgb(correction.apply(rgb), rgb);
+        }
+    }
+
+    #[test]
+    fn corrections_of_channels() {
+        assert_rgb(ColorCorrection::Brightness(0.1).apply((0.2, 0.5, 0.9)), (0.3, 0.6, 1.0));
+        assert_rgb(ColorCorrection::Contrast(2.0).apply((0.25, 0.5, 0.75)), (0.0, 0.5, 1.0));
+        assert_rgb(ColorCorrection::Exposure(1.0).apply((0.1, 0.2, 0.4)), (0.2, 0.4, 0.8));
+        assert_rgb(ColorCorrection::Gamma(2.0).apply((0.25, 0.0, 1.0)), (0.5, 0.0, 1.0));
+
+        // input [0.2,0.6] stretched to output [0.1,0.9]
+        let levels = ColorCorrection::Levels { input_black: 0.2, input_white: 0.6, gamma: 1.0, output_black: 0.1, output_white: 0.9 };
+        assert_rgb(levels.apply((0.1, 0.4, 0.8)), (0.1, 0.5, 0.9));
+    }
+
+    #[test]
+    fn corrections_of_colors() {
+        let l = luma(0.2, 0.5, 0.9);
+        assert_rgb(ColorCorrection::Saturation(0.0).apply((0.2, 0.5, 0.9)), (l, l, l));
+        assert_rgb(ColorCorrection::HueShift(360.0).apply((0.2, 0.5, 0.9)), (0.2, 0.5, 0.9));
+        assert_rgb(ColorCorrection::HueShift(180.0).apply((0.5, 0.5, 0.5)), (0.5, 0.5, 0.5));
+        assert_rgb(ColorCorrection::Tint { color: (1.0, 0.0, 0.0), amount: 0.5 }.apply((0.0, 0.5, 1.0)), (0.5, 0.25, 0.5));
+    }
+
+    #[test]
+    fn pixbufs_keep_alpha() {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, true, 8, 1, 1);
+        pixbuf.put_pixel(0, 0, 100, 150, 200, 77);
+
+        for (correction, expected) in vec![
+            (ColorCorrection::Brightness(-1.0), [0, 0, 0, 77]),
+            (ColorCorrection::Saturation(0.0), [143, 143, 143, 77]),
+        ] {
+            let corrected = correction.apply_pixbuf(pixbuf.copy().unwrap());
+            assert_eq!(unsafe { &corrected.get_pixels()[0..4] }, &expected);
+        }
+
+        // the original is left as it is
+        assert_eq!(unsafe { &pixbuf.get_pixels()[0..4] }, &[100, 150, 200, 77]);
+    }
+}
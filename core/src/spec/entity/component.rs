@@ -2,12 +2,104 @@ extern crate serde_json;
 extern crate gdk_pixbuf;
 extern crate gstreamer as gst;
 use util::*;
+use spec::{BlendMode, EffectTime, Mask, MotionBlur};
 use std::collections::HashMap;
+use std::cmp;
 
 // Component domain requires the following specifications:
 // - represents an object in timeline (start_time, end_time, length)
 // - play video and/or sound
 // - has effects
+// - placed on the screen by its geometry
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Geometry {
+    #[serde(default = "coordinate_default")]
+    pub coordinate: (i32, i32),
+
+    #[serde(default = "scale_default")]
+    pub scale: (f64, f64),
+
+    // 0 (transparent) to 255 (opaque)
+    #[serde(default = "alpha_default")]
+    pub alpha: i32,
+}
+
+impl Default for Geometry {
+    fn default() -> Geometry {
+        Geometry {
+            coordinate: coordinate_default(),
+            scale: scale_default(),
+            alpha: alpha_default(),
+        }
+    }
+}
+
+impl Geometry {
+    // geometry on a canvas scaled by the factor
+    pub fn scaled(&self, factor: f64) -> Geometry {
+        Geometry {
+            coordinate: ((self.coordinate.0 as f64 * factor).round() as i32, (self.coordinate.1 as f64 * factor).round() as i32),
+            scale: (self.scale.0 * factor, self.scale.1 * factor),
+            alpha: self.alpha,
+        }
+    }
+}
+
+// pixels removed from each edge of the frame
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Crop {
+    #[serde(default)]
+    pub left: i32,
+
+    #[serde(default)]
+    pub top: i32,
+
+    #[serde(default)]
+    pub right: i32,
+
+    #[serde(default)]
+    pub bottom: i32,
+}
+
+impl Crop {
+    // crop of the frame scaled by the factor
+    pub fn scaled(&self, factor: f64) -> Crop {
+        let scale = |edge: i32| (edge as f64 * factor).round() as i32;
+
+        Crop {
+            left: scale(self.left),
+            top: scale(self.top),
+            right: scale(self.right),
+            bottom: scale(self.bottom),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left <= 0 && self.top <= 0 && self.right <= 0 && self.bottom <= 0
+    }
+
+    // the cropped region of pixbuf and its offset, or None if nothing is left
+    pub fn crop_pixbuf(&self, pixbuf: gdk_pixbuf::Pixbuf) -> Option<(gdk_pixbuf::Pixbuf, (i32, i32))> {
+        if self.is_empty() {
+            return Some((pixbuf, (0, 0)));
+        }
+
+        let left = cmp::max(self.left, 0);
+        let top = cmp::max(self.top, 0);
+        let width = pixbuf.get_width() - left - cmp::max(self.right, 0);
+        let height = pixbuf.get_height() - top - cmp::max(self.bottom, 0);
+        if width <= 0 || height <= 0 {
+            return None;
+        }
+
+        pixbuf.new_subpixbuf(left, top, width, height).map(|cropped| (cropped, (left, top)))
+    }
+}
+
+fn coordinate_default() -> (i32, i32) { (0,0) }
+fn scale_default() -> (f64, f64) { (1.0,1.0) }
+fn alpha_default() -> i32 { 255 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Component {
@@ -26,24 +118,122 @@ pub struct Component {
     // fix type as String might be a bad idea...
     #[serde(default = "Vec::new")]
     pub effect: Vec<String>,
+
+    #[serde(default)]
+    pub geometry: Geometry,
+
+    // overrides the blend mode of the layer
+    #[serde(default)]
+    pub blend_mode: Option<BlendMode>,
+
+    // the component is visible only inside of its masks
+    #[serde(default = "Vec::new")]
+    pub masks: Vec<Mask>,
+
+    // applied before the geometry, keeping the rest of the frame in place
+    #[serde(default)]
+    pub crop: Crop,
+
+    // overrides the motion blur of the project
+    #[serde(default)]
+    pub motion_blur: Option<MotionBlur>,
+
+    // playback rate of the media, 1.0 is the native rate
+    #[serde(default = "speed_default")]
+    pub speed: f64,
+
+    // blend adjacent source frames when played at a non-native rate
+    #[serde(default)]
+    pub frame_blending: bool,
+
+    // keyframes of effects keep their time from the start when the length changes, unless rescaled
+    #[serde(default)]
+    pub rescale_keyframes: bool,
 }
 
+fn speed_default() -> f64 { 1.0 }
+
 impl Component {
     pub fn end_time(&self) -> gst::ClockTime {
         self.start_time + self.length
     }
 
-    pub fn partial_update(&mut self, value: &serde_json::Map<String, serde_json::Value>) {
+    // time in the component at the position, holding the first or last frame outside of it
+    pub fn local_time(&self, position: gst::ClockTime) -> gst::ClockTime {
+        let start = self.start_time.nseconds().unwrap_or(0);
+        let length = self.length.nseconds().unwrap_or(0);
+        let position = position.nseconds().unwrap_or(0);
+
+        cmp::min(position.saturating_sub(start), length) * gst::NSECOND
+    }
+
+    // where effects are evaluated at the local time
+    pub fn effect_time(&self, local_time: gst::ClockTime) -> EffectTime {
+        EffectTime::new(local_time, self.length)
+    }
+
+    // properties are updated at once, only if all of them are valid
+    pub fn partial_update(&mut self, value: &serde_json::Map<String, serde_json::Value>) -> Result<(), String> {
+        let mut updated = self.clone();
+
         for (k,v) in value {
+            let v = v.clone();
             match k.as_str() {
-                "start_time" => self.start_time = serde_json::from_value::<SerTime>(v.clone()).unwrap().0,
-                "length" => self.length = serde_json::from_value::<SerTime>(v.clone()).unwrap().0,
-                _ => unreachable!(),
+                "start_time" => updated.start_time = serde_json::from_value::<SerTime>(v).map_err(|t| t.to_string())?.0,
+                "length" => updated.length = serde_json::from_value::<SerTime>(v).map_err(|t| t.to_string())?.0,
+                "geometry" => updated.geometry = serde_json::from_value(v).map_err(|t| t.to_string())?,
+                "blend_mode" => updated.blend_mode = serde_json::from_value(v).map_err(|t| t.to_string())?,
+                "masks" => updated.masks = serde_json::from_value(v).map_err(|t| t.to_string())?,
+                "crop" => updated.crop = serde_json::from_value(v).map_err(|t| t.to_string())?,
+                "motion_blur" => updated.motion_blur = serde_json::from_value(v).map_err(|t| t.to_string())?,
+                "speed" => {
+                    let speed: f64 = serde_json::from_value(v).map_err(|t| t.to_string())?;
+                    if speed <= 0.0 {
+                        return Err("speed should be positive".to_string());
+                    }
+
+                    updated.speed = speed;
+                },
+                "frame_blending" => updated.frame_blending = serde_json::from_value(v).map_err(|t| t.to_string())?,
+                "rescale_keyframes" => updated.rescale_keyframes = serde_json::from_value(v).map_err(|t| t.to_string())?,
+                _ => return Err(format!("Component has no such property: {}", k)),
             }
         }
+
+        *self = updated;
+
+        Ok(())
+    }
+
+    // crop is exposed as an attribute as well
+    pub fn get_attribute(&self, key: &str) -> Option<serde_json::Value> {
+        match key {
+            "crop" => Some(json!(self.crop)),
+            _ => self.attributes.get(key).cloned(),
+        }
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: serde_json::Value) -> Result<(), String> {
+        match key {
+            "crop" => self.crop = serde_json::from_value(value).map_err(|t| t.to_string())?,
+            _ => { self.attributes.insert(key.to_string(), value); },
+        }
+
+        Ok(())
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProxyStatus {
+    // the component has no media to be transcoded
+    Unsupported,
+    NotGenerated,
+    // in progress of [0,1]
+    Generating(f64),
+    Ready,
+    Failed(String),
+}
+
 pub trait HaveComponent {
     fn component(&self) -> &Component;
     fn component_mut(&mut self) -> &mut Component;
@@ -52,6 +242,40 @@ pub trait HaveComponent {
         None
     }
 
+    // a frame for preview, decoded from the proxy if available
+    fn get_preview_pixbuf(&self, time: gst::ClockTime) -> Option<gdk_pixbuf::Pixbuf> {
+        self.get_pixbuf(time)
+    }
+
+    fn proxy_status(&self) -> ProxyStatus {
+        ProxyStatus::Unsupported
+    }
+
+    // transcode a low-resolution copy of the media into the cache directory
+    fn generate_proxy(&mut self, &str) -> Result<(), String> {
+        Err("Proxy is not supported for this component".to_string())
+    }
+
+    // size of the original media, if preview frames can be smaller than it
+    fn source_size(&self) -> Option<(i32, i32)> {
+        None
+    }
+
+    // effects of an adjustment apply to the layers below, instead of its own pixels
+    fn is_adjustment(&self) -> bool {
+        false
+    }
+
+    // name of the composition rendered in place of media
+    fn composition(&self) -> Option<&str> {
+        None
+    }
+
+    // native frame rate of the media, if known
+    fn frame_rate(&self) -> Option<f64> {
+        None
+    }
+
     fn get_audio_elements(&self) -> Vec<gst::Element> {
         vec![]
     }
@@ -60,3 +284,47 @@ pub trait HaveComponent {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crop(left: i32, top: i32, right: i32, bottom: i32) -> Crop {
+        Crop { left: left, top: top, right: right, bottom: bottom }
+    }
+
+    // a pixbuf whose red is x and green is y of each pixel
+    fn coordinates(width: i32, height: i32) -> gdk_pixbuf::Pixbuf {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, true, 8, width, height);
+        for y in 0..height {
+            for x in 0..width {
+                pixbuf.put_pixel(x, y, x as u8, y as u8, 0, 255);
+            }
+        }
+        pixbuf
+    }
+
+    #[test]
+    fn crop_edges() {
+        let (cropped, offset) = crop(1, 2, 0, 1).crop_pixbuf(coordinates(4, 5)).unwrap();
+        let pixels = unsafe { cropped.get_pixels() };
+
+        assert_eq!((cropped.get_width(), cropped.get_height()), (3, 2));
+        assert_eq!(offset, (1, 2));
+        assert_eq!((pixels[0], pixels[1]), (1, 2));
+
+        // negative edges are ignored, and nothing left is no frame
+        let (uncropped, offset) = crop(-1, 0, -3, 0).crop_pixbuf(coordinates(4, 5)).unwrap();
+        assert_eq!((uncropped.get_width(), uncropped.get_height(), offset), (4, 5, (0, 0)));
+        assert!(crop(2, 0, 2, 0).crop_pixbuf(coordinates(4, 5)).is_none());
+        assert!(crop(0, 5, 0, 0).crop_pixbuf(coordinates(4, 5)).is_none());
+    }
+
+    #[test]
+    fn crop_scaled_for_proxies() {
+        let scaled = crop(10, 5, 3, 0).scaled(0.5);
+        assert_eq!((scaled.left, scaled.top, scaled.right, scaled.bottom), (5, 3, 2, 0));
+        assert!(crop(0, -1, 0, 0).is_empty());
+        assert!(!crop(0, 0, 0, 1).is_empty());
+    }
+}
@@ -1,14 +1,62 @@
 extern crate gdk_pixbuf;
+extern crate gstreamer as gst;
+extern crate serde_json;
 use gdk_pixbuf::prelude::*;
 use std::f32::consts::PI;
+use std::collections::HashMap;
+use std::mem;
+use spec::*;
 
+// a keyframe, where the transition leads into it
 #[derive(Clone, Serialize, Deserialize)]
 pub struct EffectPoint {
     transition: Transition,
-    position: f32,
-    value: f32,
+
+    // milliseconds from the start of the component
+    position: u64,
+    value: EffectValue,
+}
+
+impl EffectPoint {
+    pub fn validate(&self) -> Result<(), String> {
+        self.transition.validate()
+    }
+}
+
+// where effects are evaluated, in milliseconds from the start of the component
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EffectTime {
+    pub time: f64,
+    pub length: f64,
+}
+
+impl EffectTime {
+    pub fn new(time: gst::ClockTime, length: gst::ClockTime) -> EffectTime {
+        EffectTime {
+            time: time.nseconds().unwrap_or(0) as f64 / 1_000_000.0,
+            length: length.nseconds().unwrap_or(0) as f64 / 1_000_000.0,
+        }
+    }
+}
+
+fn interpolate(from: (f64, &EffectValue), to: (f64, &EffectValue), transition: &Transition, value_type: ValueType, time: f64) -> EffectValue {
+    let span = to.0 - from.0;
+    let x = if span > 0.0 { ((time - from.0) / span) as f32 } else { 1.0 };
+
+    from.1.mix(to.1, transition.get_in_interval(x), value_type)
 }
 
+// where the jumps of Steps happen, following steps() of CSS easing functions
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StepJump {
+    JumpStart,
+    JumpEnd,
+    JumpNone,
+    JumpBoth,
+}
+
+fn step_jump_default() -> StepJump { StepJump::JumpEnd }
+
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Transition {
     Linear,
@@ -16,6 +64,37 @@ pub enum Transition {
     EaseIn,
     EaseOut,
     EaseInOut,
+
+    // cubic-bezier() of CSS, where x1 and x2 should be in [0,1]
+    CubicBezier {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+    },
+
+    Steps {
+        count: u32,
+
+        #[serde(default = "step_jump_default")]
+        jump: StepJump,
+    },
+
+    // keeps the start value until the end
+    Hold,
+
+    // damped spring from rest, in the time unit where the interval is 1,
+    // that should settle within the interval since the value snaps to the end at last
+    Spring {
+        stiffness: f32,
+        damping: f32,
+    },
+
+    // the following easings overshoot or oscillate, except Bounce
+    Bounce,
+    Elastic,
+    BackIn,
+    BackOut,
 }
 
 impl Transition {
@@ -28,10 +107,34 @@ impl Transition {
             EaseIn,
             EaseOut,
             EaseInOut,
+            Hold,
+            Spring { stiffness: 100.0, damping: 10.0 },
+            Bounce,
+            Elastic,
+            BackIn,
+            BackOut,
         ]
     }
 
-    fn get_in_interval(&self, x: f32) -> f32 {
+    pub fn validate(&self) -> Result<(), String> {
+        use Transition::*;
+
+        match self {
+            &CubicBezier { x1, x2, .. } if !(0.0 <= x1 && x1 <= 1.0 && 0.0 <= x2 && x2 <= 1.0) => {
+                Err("x1 and x2 of CubicBezier should be in [0,1]".to_string())
+            },
+            &Steps { count, jump } if count < 1 || (jump == StepJump::JumpNone && count < 2) => {
+                Err("Steps should have at least 1 step, or 2 steps for JumpNone".to_string())
+            },
+            &Spring { stiffness, damping } if !(stiffness > 0.0 && damping > 0.0) => {
+                Err("stiffness and damping of Spring should be positive".to_string())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // the result may be out of [0,1] for overshooting easings
+    pub fn get_in_interval(&self, x: f32) -> f32 {
         use Transition::*;
 
         match self {
@@ -40,9 +143,99 @@ impl Transition {
             &EaseIn => Transition::cubic_bezier(0.42, 0.0, 1.0, 1.0, x),
             &EaseOut => Transition::cubic_bezier(0.0, 0.0, 0.58, 1.0, x),
             &EaseInOut => Transition::cubic_bezier(0.42, 0.0, 0.58, 1.0, x),
+            &CubicBezier { x1, y1, x2, y2 } => Transition::cubic_bezier(x1, y1, x2, y2, x),
+            &Steps { count, jump } => Transition::steps(count, jump, x),
+            &Hold => if x < 1.0 { 0.0 } else { 1.0 },
+            &Spring { stiffness, damping } => Transition::spring(stiffness, damping, x),
+            &Bounce => Transition::bounce(x),
+            &Elastic => Transition::elastic(x),
+            &BackIn => Transition::back_in(x),
+            &BackOut => 1.0 - Transition::back_in(1.0 - x),
+        }
+    }
+
+    fn spring(stiffness: f32, damping: f32, x: f32) -> f32 {
+        if x >= 1.0 {
+            return 1.0;
+        }
+
+        // x'' = -stiffness (x - 1) - damping x', from x = 0 and x' = 0
+        let t = x.max(0.0) as f64;
+        let omega = (stiffness as f64).max(0.0).sqrt();
+        let zeta = damping as f64 / (2.0 * omega);
+
+        let y = if zeta < 1.0 {
+            let omega_d = omega * (1.0 - zeta * zeta).sqrt();
+            1.0 - (-zeta * omega * t).exp() * ((omega_d * t).cos() + zeta * omega / omega_d * (omega_d * t).sin())
+        } else if zeta == 1.0 {
+            1.0 - (-omega * t).exp() * (1.0 + omega * t)
+        } else {
+            let r1 = -omega * (zeta - (zeta * zeta - 1.0).sqrt());
+            let r2 = -omega * (zeta + (zeta * zeta - 1.0).sqrt());
+            1.0 - (r2 * (r1 * t).exp() - r1 * (r2 * t).exp()) / (r2 - r1)
+        };
+
+        if y.is_finite() { y as f32 } else { x }
+    }
+
+    // easeOutBounce of Robert Penner's easing functions
+    fn bounce(x: f32) -> f32 {
+        const N: f32 = 7.5625;
+        const D: f32 = 2.75;
+
+        let x = x.max(0.0).min(1.0);
+        if x < 1.0 / D {
+            N * x * x
+        } else if x < 2.0 / D {
+            let x = x - 1.5 / D;
+            N * x * x + 0.75
+        } else if x < 2.5 / D {
+            let x = x - 2.25 / D;
+            N * x * x + 0.9375
+        } else {
+            let x = x - 2.625 / D;
+            N * x * x + 0.984375
         }
     }
 
+    // easeOutElastic of Robert Penner's easing functions
+    fn elastic(x: f32) -> f32 {
+        if x <= 0.0 {
+            0.0
+        } else if x >= 1.0 {
+            1.0
+        } else {
+            2.0f32.powf(-10.0 * x) * ((10.0 * x - 0.75) * 2.0 * PI / 3.0).sin() + 1.0
+        }
+    }
+
+    // easeInBack of Robert Penner's easing functions, which goes below 0 first
+    fn back_in(x: f32) -> f32 {
+        const OVERSHOOT: f32 = 1.70158;
+
+        let x = x.max(0.0).min(1.0);
+        (OVERSHOOT + 1.0) * x * x * x - OVERSHOOT * x * x
+    }
+
+    fn steps(count: u32, jump: StepJump, x: f32) -> f32 {
+        let count = count as f32;
+        let jumps = match jump {
+            StepJump::JumpNone => count - 1.0,
+            StepJump::JumpBoth => count + 1.0,
+            _ => count,
+        };
+        if jumps <= 0.0 {
+            return x;
+        }
+
+        let mut step = (x * count).floor();
+        if jump == StepJump::JumpStart || jump == StepJump::JumpBoth {
+            step += 1.0;
+        }
+
+        step.max(0.0).min(jumps) / jumps
+    }
+
     fn cubic_bezier(p0: f32, p1: f32, p2: f32, p3: f32, x: f32) -> f32 {
         // cubic bezier calculation by Newton method
         //
@@ -52,7 +245,8 @@ impl Transition {
         //
         // x' = 3 (3 P2.x - 3 P3.x + 1) t^2 + 2 (-6 P2.x + 3 P3.x) t + 3 P2.x
         const MAX_ITERATION: i32 = 50;
-        const NEIGHBOR: f32 = 0.01;
+        const EPSILON: f32 = 1e-6;
+        const MIN_SLOPE: f32 = 1e-3;
 
         fn _bezier_params(u: f32, v: f32) -> (f32, f32, f32) {
             let k3 = 3.0 * u - 3.0 * v + 1.0;
@@ -78,22 +272,48 @@ impl Transition {
 
         let get_t_at_x = |x: f32| {
             let mut t = x;
-            let mut new_t = x;
-
             for _ in 0..MAX_ITERATION {
                 let f_t = bezier_x(t) - x;
+                if f_t.abs() < EPSILON {
+                    return t;
+                }
+
+                // the curve is flat around t, where Newton method jumps away
                 let fp_t = bezier_dt_x(t);
-                new_t = t - (f_t / fp_t);
-                if (new_t - t).abs() < NEIGHBOR {
+                if fp_t.abs() < MIN_SLOPE {
                     break;
                 }
 
-                t = new_t;
+                t -= f_t / fp_t;
             }
 
-            new_t
+            // bisection, since x is monotonic in t as x1 and x2 are in [0,1]
+            let (mut lower, mut upper) = (0.0, 1.0);
+            let mut t = x;
+            for _ in 0..MAX_ITERATION {
+                let f_t = bezier_x(t) - x;
+                if f_t.abs() < EPSILON {
+                    break;
+                }
+
+                if f_t > 0.0 {
+                    upper = t;
+                } else {
+                    lower = t;
+                }
+                t = (lower + upper) / 2.0;
+            }
+
+            t
         };
 
+        if x <= 0.0 {
+            return 0.0;
+        }
+        if x >= 1.0 {
+            return 1.0;
+        }
+
         bezier_y(get_t_at_x(x))
     }
 }
@@ -102,12 +322,224 @@ impl Transition {
 pub struct Effect {
     pub effect_type: String,
     pub transition: Transition,
-    pub start_value: f32,
-    pub end_value: f32,
+
+    // the type of start_value, end_value and values of keyframes
+    #[serde(default)]
+    pub value_type: ValueType,
+
+    pub start_value: EffectValue,
+    pub end_value: EffectValue,
     pub intervals: Vec<EffectPoint>,
+
+    // parameters which are not animated, such as levels
+    #[serde(default = "HashMap::new")]
+    pub params: HashMap<String, serde_json::Value>,
+
+    // evaluated on top of the keyframes, where `value` is the value of them
+    #[serde(default)]
+    pub expression: Option<String>,
+
+    // the expression parsed by parse_expression, not to parse it on every frame
+    #[serde(skip)]
+    parsed_expression: Option<Expr>,
+
+    // values of the effects referred by the expression at the frame, bound by EffectRepository::resolve
+    #[serde(skip)]
+    pub referred: HashMap<String, f32>,
 }
 
 impl Effect {
+    pub fn effect_types() -> Vec<&'static str> {
+        vec![
+            "rotate",
+            "coordinate_x",
+            "coordinate_y",
+            "scale_x",
+            "scale_y",
+            "alpha",
+            "crop_left",
+            "crop_top",
+            "crop_right",
+            "crop_bottom",
+            "coordinate",
+            "scale",
+            "tint",
+            "brightness",
+            "contrast",
+            "saturation",
+            "hue_shift",
+            "gamma",
+            "levels",
+            "exposure",
+            "blur",
+            "directional_blur",
+            "sharpen",
+            "glow",
+            "chroma_key",
+            "lut",
+        ]
+    }
+
+    // masks are animated by "mask.{index}.{name}", see Mask::set_param
+    pub fn validate_type(effect_type: &str) -> Result<(), String> {
+        if Effect::effect_types().contains(&effect_type) {
+            return Ok(());
+        }
+
+        if effect_type.starts_with("mask.") {
+            return match Effect::parse_mask_param(effect_type) {
+                Some((_, name)) => Mask::validate_param_name(name).map_err(|err| format!("Invalid effect_type {}: {}", effect_type, err)),
+                None => Err(format!("Invalid effect_type {}: expected mask.{{index}}.{{name}}", effect_type)),
+            };
+        }
+
+        Err(format!("Unknown effect_type: {}", effect_type))
+    }
+
+    fn parse_mask_param(effect_type: &str) -> Option<(usize, &str)> {
+        let path = effect_type.splitn(3, '.').collect::<Vec<&str>>();
+
+        match (path.get(0), path.get(1), path.get(2)) {
+            (Some(&"mask"), Some(index), Some(name)) => index.parse::<usize>().ok().map(|index| (index, *name)),
+            _ => None,
+        }
+    }
+
+    // the index of the mask and the name of its parameter, if the effect animates a mask
+    pub fn mask_param(&self) -> Option<(usize, &str)> {
+        Effect::parse_mask_param(&self.effect_type)
+    }
+
+    // keep keyframes sorted by position, replacing the one at the same position
+    pub fn insert_keyframe(&mut self, point: EffectPoint) {
+        match self.intervals.binary_search_by_key(&point.position, |p| p.position) {
+            Ok(index) => self.intervals[index] = point,
+            Err(index) => self.intervals.insert(index, point),
+        }
+    }
+
+    // sort keyframes given at once, where the later one wins at the same position
+    pub fn normalize_keyframes(&mut self) {
+        for point in mem::replace(&mut self.intervals, vec![]) {
+            self.insert_keyframe(point);
+        }
+    }
+
+    // move keyframes along with the length of the component
+    pub fn rescale_keyframes(&mut self, old_length: gst::ClockTime, new_length: gst::ClockTime) {
+        let old_length = old_length.mseconds().unwrap_or(0);
+        let new_length = new_length.mseconds().unwrap_or(0);
+        if old_length == 0 {
+            return;
+        }
+
+        for point in &mut self.intervals {
+            point.position = (point.position as f64 * new_length as f64 / old_length as f64).round() as u64;
+        }
+        self.normalize_keyframes();
+    }
+
+    pub fn validate_transitions(&self) -> Result<(), String> {
+        self.transition.validate()?;
+        for point in &self.intervals {
+            point.validate()?;
+        }
+
+        Ok(())
+    }
+
+    // the value type which the effect type takes
+    fn accepts(&self, value_type: ValueType) -> bool {
+        match (self.effect_type.as_str(), value_type) {
+            ("coordinate", ValueType::Point) | ("scale", ValueType::Point) => true,
+            ("tint", ValueType::Color { .. }) => true,
+            ("rotate", ValueType::Angle) | ("hue_shift", ValueType::Angle) => true,
+            ("coordinate", _) | ("scale", _) | ("tint", _) => false,
+            (_, value_type) => value_type == ValueType::Scalar,
+        }
+    }
+
+    pub fn validate_values(&self) -> Result<(), String> {
+        if !self.accepts(self.value_type) {
+            return Err(format!("{} does not take values of {:?}", self.effect_type, self.value_type));
+        }
+
+        let values = vec![&self.start_value, &self.end_value].into_iter().chain(self.intervals.iter().map(|point| &point.value));
+        for value in values {
+            if !value.is_of(self.value_type) {
+                return Err(format!("Value {:?} is not of {:?}", value, self.value_type));
+            }
+        }
+
+        Ok(())
+    }
+
+    // expressions give a number, thus only for Scalar and Angle.
+    // should be called whenever the effect is deserialized
+    pub fn parse_expression(&mut self) -> Result<(), String> {
+        self.parsed_expression = match self.expression {
+            Some(ref expression) => {
+                match self.value_type {
+                    ValueType::Scalar | ValueType::Angle => (),
+                    value_type => return Err(format!("Expression can not give values of {:?}", value_type)),
+                }
+
+                Some(Expr::parse(expression)?)
+            },
+            None => None,
+        };
+
+        Ok(())
+    }
+
+    // ids of the effects referred by the expression
+    pub fn references(&self) -> Vec<String> {
+        self.parsed_expression.as_ref().map(|expr| expr.references()).unwrap_or(vec![])
+    }
+
+    // properties are updated at once, only if the result is valid
+    pub fn partial_update(&mut self, value: &serde_json::Map<String, serde_json::Value>) -> Result<(), String> {
+        let mut updated = self.clone();
+
+        for (k,v) in value {
+            match k.as_str() {
+                "effect_type" => {
+                    let effect_type: String = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?;
+                    Effect::validate_type(&effect_type)?;
+                    updated.effect_type = effect_type;
+                },
+                "transition" => {
+                    let transition: Transition = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?;
+                    transition.validate()?;
+                    updated.transition = transition;
+                },
+                "value_type" => updated.value_type = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "start_value" => updated.start_value = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "end_value" => updated.end_value = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "params" => updated.params = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "expression" => updated.expression = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                _ => return Err(format!("Effect has no such property: {}", k)),
+            }
+        }
+
+        updated.validate_values()?;
+        updated.parse_expression()?;
+        *self = updated;
+
+        Ok(())
+    }
+
+    pub fn param_f32(&self, key: &str, default: f32) -> f32 {
+        self.params.get(key).and_then(|v| v.as_f64()).map(|v| v as f32).unwrap_or(default)
+    }
+
+    // a color given as {red, green, blue}
+    pub fn param_color(&self, key: &str, default: (u8, u8, u8)) -> (u8, u8, u8) {
+        self.params.get(key).and_then(|v| {
+            Some((v.get("red")?.as_u64()? as u8, v.get("green")?.as_u64()? as u8, v.get("blue")?.as_u64()? as u8))
+        }).unwrap_or(default)
+    }
+
     pub fn rotate(arg: f32, x: i32, y: i32) -> (i32, i32) {
         ((x as f32 * arg.cos() + y as f32 * arg.sin()) as i32,
          (x as f32 * -arg.sin() + y as f32 * arg.cos()) as i32,
@@ -121,7 +553,7 @@ impl Effect {
         (pixels[pos],
          pixels[pos + 1],
          pixels[pos + 2],
-         if pixbuf.get_has_alpha() { pixels[pos + 3] } else { 0 },
+         if pixbuf.get_has_alpha() { pixels[pos + 3] } else { 255 },
         )
     }
 
@@ -154,7 +586,7 @@ impl Effect {
                         new_pixbuf.put_pixel(ix, iy, r, g, b, a);
                     }
                 else {
-                    new_pixbuf.put_pixel(ix, iy, 0, 0, 0, 255);
+                    new_pixbuf.put_pixel(ix, iy, 0, 0, 0, 0);
                 }
             }
         }
@@ -162,32 +594,254 @@ impl Effect {
         new_pixbuf
     }
 
-    pub fn effect_on_pixbuf(&self, pixbuf: gdk_pixbuf::Pixbuf, current: f32) -> gdk_pixbuf::Pixbuf {
+    // levels is animated by its gamma, other parameters are given by params
+    pub fn color_correction(&self, current: EffectTime) -> Option<ColorCorrection> {
+        use ColorCorrection::*;
+
+        let value = self.scalar(current);
+        match self.effect_type.as_str() {
+            "brightness" => Some(Brightness(value)),
+            "contrast" => Some(Contrast(value)),
+            "saturation" => Some(Saturation(value)),
+            "hue_shift" => Some(HueShift(value)),
+            "gamma" => Some(Gamma(value)),
+            "exposure" => Some(Exposure(value)),
+            "tint" => match self.value(current) {
+                EffectValue::Color { red, green, blue, alpha } => Some(Tint {
+                    color: (red / 255.0, green / 255.0, blue / 255.0),
+                    amount: alpha / 255.0,
+                }),
+                _ => None,
+            },
+            "levels" => Some(Levels {
+                input_black: self.param_f32("input_black", 0.0),
+                input_white: self.param_f32("input_white", 1.0),
+                gamma: value,
+                output_black: self.param_f32("output_black", 0.0),
+                output_white: self.param_f32("output_white", 1.0),
+            }),
+            _ => None,
+        }
+    }
+
+    // the animated value is the radius of blur, the length of directional_blur,
+    // the amount of sharpen and the intensity of glow
+    pub fn filter(&self, current: EffectTime) -> Option<Filter> {
+        use Filter::*;
+
+        let value = self.scalar(current);
         match self.effect_type.as_str() {
-            "rotate" => Effect::get_rotated_pixbuf(pixbuf, self.value(current)),
+            "blur" => Some(Blur(value)),
+            "directional_blur" => Some(DirectionalBlur {
+                length: value,
+                angle: self.param_f32("angle", 0.0),
+            }),
+            "sharpen" => Some(Sharpen {
+                amount: value,
+                radius: self.param_f32("radius", 2.0),
+            }),
+            "glow" => Some(Glow {
+                intensity: value,
+                radius: self.param_f32("radius", 10.0),
+                threshold: self.param_f32("threshold", 0.5),
+            }),
+            _ => None,
+        }
+    }
+
+    // the animated value is the tolerance
+    pub fn chroma_key(&self, current: EffectTime) -> Option<ChromaKey> {
+        match self.effect_type.as_str() {
+            "chroma_key" => Some(ChromaKey {
+                key_color: self.param_color("key_color", (0, 255, 0)),
+                tolerance: self.scalar(current),
+                softness: self.param_f32("softness", 0.1),
+                spill: self.param_f32("spill", 0.5),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn lut_name(&self) -> Option<&str> {
+        match self.effect_type.as_str() {
+            "lut" => self.params.get("lut").and_then(|v| v.as_str()),
+            _ => None,
+        }
+    }
+
+    // the name of LUT in the project, and the intensity as the animated value
+    pub fn lut(&self, current: EffectTime) -> Option<(&str, f32)> {
+        self.lut_name().map(|name| (name, self.scalar(current)))
+    }
+
+    // lut effects are applied by the presenter, since LUTs are loaded in the project
+    pub fn effect_on_pixbuf(&self, pixbuf: gdk_pixbuf::Pixbuf, current: EffectTime) -> gdk_pixbuf::Pixbuf {
+        if let Some(chroma_key) = self.chroma_key(current) {
+            return chroma_key.apply_pixbuf(pixbuf);
+        }
+
+        if let Some(correction) = self.color_correction(current) {
+            return correction.apply_pixbuf(pixbuf);
+        }
+
+        if let Some(filter) = self.filter(current) {
+            return filter.apply_pixbuf(pixbuf);
+        }
+
+        match self.effect_type.as_str() {
+            "rotate" => Effect::get_rotated_pixbuf(pixbuf, self.scalar(current)),
             _ => pixbuf,
         }
     }
 
-    pub fn value(&self, current: f32) -> f32 {
-        let find_corresponding_interval = || -> (f32, f32, f32, Transition) {
-            let mut prev_time = 0.0;
-            let mut prev_value = self.start_value;
+    pub fn effect_on_geometry(&self, geometry: Geometry, current: EffectTime) -> Geometry {
+        let mut geometry = geometry;
 
-            for intermed in &self.intervals {
-                if current <= intermed.position {
-                    return ((current - prev_time) / (intermed.position - prev_time), prev_value, intermed.value, intermed.transition.clone())
+        match self.effect_type.as_str() {
+            "coordinate_x" => geometry.coordinate.0 = self.scalar(current) as i32,
+            "coordinate_y" => geometry.coordinate.1 = self.scalar(current) as i32,
+            "scale_x" => geometry.scale.0 = self.scalar(current) as f64,
+            "scale_y" => geometry.scale.1 = self.scalar(current) as f64,
+            "alpha" => geometry.alpha = self.scalar(current) as i32,
+            "coordinate" | "scale" => if let EffectValue::Point { x, y } = self.value(current) {
+                if self.effect_type == "coordinate" {
+                    geometry.coordinate = (x as i32, y as i32);
+                } else {
+                    geometry.scale = (x as f64, y as f64);
                 }
+            },
+            _ => (),
+        }
+
+        geometry
+    }
+
+    pub fn effect_on_crop(&self, crop: Crop, current: EffectTime) -> Crop {
+        let mut crop = crop;
+
+        match self.effect_type.as_str() {
+            "crop_left" => crop.left = self.scalar(current) as i32,
+            "crop_top" => crop.top = self.scalar(current) as i32,
+            "crop_right" => crop.right = self.scalar(current) as i32,
+            "crop_bottom" => crop.bottom = self.scalar(current) as i32,
+            _ => (),
+        }
+
+        crop
+    }
+
+    // effect_type is "mask.{index}.{name}", see Mask::set_param
+    pub fn effect_on_masks(&self, masks: Vec<Mask>, current: EffectTime) -> Vec<Mask> {
+        let mut masks = masks;
 
-                prev_time = intermed.position;
-                prev_value = intermed.value;
+        // parameters are validated on creation, but the mask may have been removed or reshaped since then
+        if let Some((index, name)) = self.mask_param() {
+            if let Some(mask) = masks.get_mut(index) {
+                let _ = mask.set_param(name, self.scalar(current) as f64);
             }
+        }
+
+        masks
+    }
 
-            (current, prev_value, self.end_value, self.transition.clone())
+    // from start_value at the start to end_value at the end of the component through keyframes,
+    // where keyframes at or after the end are kept but unused until the component gets longer
+    pub fn value(&self, current: EffectTime) -> EffectValue {
+        let curve = self.curve(current);
+        let expr = match self.parsed_expression {
+            Some(ref expr) => expr,
+            None => return curve,
         };
 
-        let (current, start, end, transition) = find_corresponding_interval();
-        start + transition.get_in_interval(current) * (end - start)
+        let referred = |effect_id: &str| self.referred.get(effect_id).cloned();
+        let scope = ExprScope {
+            time: (current.time / 1000.0) as f32,
+            length: (current.length / 1000.0) as f32,
+            value: curve.scalar(),
+            referred: &referred,
+        };
+
+        // the keyframes are kept on errors, such as a reference to a deleted effect
+        match expr.eval(&scope) {
+            Ok(value) if value.is_finite() => EffectValue::Number(value),
+            _ => curve,
+        }
+    }
+
+    // the value interpolated between keyframes
+    fn curve(&self, current: EffectTime) -> EffectValue {
+        let mut prev = (0.0, &self.start_value);
+
+        for point in self.intervals.iter().filter(|point| (point.position as f64) < current.length) {
+            let position = point.position as f64;
+            if current.time <= position {
+                return interpolate(prev, (position, &point.value), &point.transition, self.value_type, current.time);
+            }
+
+            prev = (position, &point.value);
+        }
+
+        interpolate(prev, (current.length, &self.end_value), &self.transition, self.value_type, current.time)
+    }
+
+    // the value of effects which take a number
+    pub fn scalar(&self, current: EffectTime) -> f32 {
+        self.value(current).scalar()
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<f32> {
+        (0..101).map(|i| i as f32 / 100.0).collect()
+    }
+
+    #[test]
+    fn easings_start_and_end() {
+        for transition in Transition::transitions() {
+            assert_eq!(transition.get_in_interval(0.0), 0.0);
+            assert_eq!(transition.get_in_interval(1.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn cubic_bezier_with_flat_ends() {
+        let transition = Transition::CubicBezier { x1: 0.0, y1: 1.0, x2: 1.0, y2: 0.0 };
+
+        for x in samples().into_iter().chain(vec![-0.5, 1e-4, 1.0 - 1e-4, 1.5]) {
+            assert!(transition.get_in_interval(x).is_finite(), "{}", x);
+        }
+        assert!((transition.get_in_interval(0.5) - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cubic_bezier_accuracy() {
+        // y = x when both control points are on the diagonal
+        let transition = Transition::CubicBezier { x1: 0.1, y1: 0.1, x2: 0.9, y2: 0.9 };
+
+        for x in samples() {
+            assert!((transition.get_in_interval(x) - x).abs() < 1e-4, "{}", x);
+        }
+    }
+
+    #[test]
+    fn ease_is_monotonic() {
+        for transition in vec![Transition::Ease, Transition::EaseIn, Transition::EaseOut, Transition::EaseInOut] {
+            let values = samples().into_iter().map(|x| transition.get_in_interval(x)).collect::<Vec<f32>>();
+            assert!(values.windows(2).all(|pair| pair[0] <= pair[1] + 1e-6));
+        }
+    }
+
+    #[test]
+    fn steps_jumps() {
+        let steps = |jump| Transition::Steps { count: 4, jump: jump };
+
+        assert_eq!(steps(StepJump::JumpEnd).get_in_interval(0.3), 0.25);
+        assert_eq!(steps(StepJump::JumpStart).get_in_interval(0.3), 0.5);
+        assert_eq!(steps(StepJump::JumpNone).get_in_interval(0.3), 1.0 / 3.0);
+        assert_eq!(steps(StepJump::JumpBoth).get_in_interval(0.3), 0.4);
+    }
+}
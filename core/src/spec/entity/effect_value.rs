@@ -0,0 +1,250 @@
+// the space where colors are interpolated
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ColorSpace {
+    Srgb,
+    LinearRgb,
+
+    // hue takes the shortest path around the wheel
+    Hsv,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ValueType {
+    Scalar,
+    Point,
+    Color {
+        space: ColorSpace,
+    },
+
+    // in degrees, interpolated along the shortest path
+    Angle,
+}
+
+impl Default for ValueType {
+    fn default() -> ValueType {
+        ValueType::Scalar
+    }
+}
+
+// a value of effects, where a number is written as it is for Scalar and Angle
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EffectValue {
+    Number(f32),
+    Point {
+        x: f32,
+        y: f32,
+    },
+
+    // channels in [0,255]
+    Color {
+        red: f32,
+        green: f32,
+        blue: f32,
+
+        #[serde(default = "opaque")]
+        alpha: f32,
+    },
+}
+
+fn opaque() -> f32 { 255.0 }
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// the difference from a to b in (-180,180]
+fn angle_diff(a: f32, b: f32) -> f32 {
+    let d = (b - a) % 360.0;
+    if d > 180.0 {
+        d - 360.0
+    } else if d <= -180.0 {
+        d + 360.0
+    } else {
+        d
+    }
+}
+
+fn to_linear(c: f32) -> f32 {
+    let c = c.max(0.0).min(1.0);
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn from_linear(c: f32) -> f32 {
+    let c = c.max(0.0).min(1.0);
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+// hue in degrees, saturation and value in [0,1]
+fn to_hsv(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    (if hue < 0.0 { hue + 360.0 } else { hue }, if max > 0.0 { delta / max } else { 0.0 }, max)
+}
+
+fn from_hsv(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
+    let h = h % 360.0;
+    let h = if h < 0.0 { h + 360.0 } else { h } / 60.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = v - c;
+
+    let (r, g, b) = match h as i32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    (r + m, g + m, b + m)
+}
+
+impl EffectValue {
+    // the value of Scalar and Angle, other types have no scalar
+    pub fn scalar(&self) -> f32 {
+        match self {
+            &EffectValue::Number(v) => v,
+            _ => 0.0,
+        }
+    }
+
+    pub fn is_of(&self, value_type: ValueType) -> bool {
+        match (self, value_type) {
+            (&EffectValue::Number(_), ValueType::Scalar) | (&EffectValue::Number(_), ValueType::Angle) => true,
+            (&EffectValue::Point { .. }, ValueType::Point) => true,
+            (&EffectValue::Color { .. }, ValueType::Color { .. }) => true,
+            _ => false,
+        }
+    }
+
+    // the value between self and other at t, which can be out of [0,1] for overshooting easings
+    pub fn mix(&self, other: &EffectValue, t: f32, value_type: ValueType) -> EffectValue {
+        use EffectValue::*;
+
+        match (self, other, value_type) {
+            (&Number(a), &Number(b), ValueType::Angle) => Number(a + angle_diff(a, b) * t),
+            (&Number(a), &Number(b), _) => Number(lerp(a, b, t)),
+            (&Point { x: x1, y: y1 }, &Point { x: x2, y: y2 }, _) => Point {
+                x: lerp(x1, x2, t),
+                y: lerp(y1, y2, t),
+            },
+            (&Color { red: r1, green: g1, blue: b1, alpha: a1 }, &Color { red: r2, green: g2, blue: b2, alpha: a2 }, value_type) => {
+                let space = match value_type {
+                    ValueType::Color { space } => space,
+                    _ => ColorSpace::Srgb,
+                };
+                let (r, g, b) = EffectValue::mix_rgb((r1 / 255.0, g1 / 255.0, b1 / 255.0), (r2 / 255.0, g2 / 255.0, b2 / 255.0), t, space);
+
+                Color {
+                    red: r * 255.0,
+                    green: g * 255.0,
+                    blue: b * 255.0,
+                    alpha: lerp(a1, a2, t).max(0.0).min(255.0),
+                }
+            },
+
+            // values of different types jump in the middle
+            _ => if t < 0.5 { self.clone() } else { other.clone() },
+        }
+    }
+
+    fn mix_rgb(a: (f32, f32, f32), b: (f32, f32, f32), t: f32, space: ColorSpace) -> (f32, f32, f32) {
+        let clamp = |c: f32| c.max(0.0).min(1.0);
+
+        match space {
+            ColorSpace::Srgb => (clamp(lerp(a.0, b.0, t)), clamp(lerp(a.1, b.1, t)), clamp(lerp(a.2, b.2, t))),
+            ColorSpace::LinearRgb => (
+                from_linear(lerp(to_linear(a.0), to_linear(b.0), t)),
+                from_linear(lerp(to_linear(a.1), to_linear(b.1), t)),
+                from_linear(lerp(to_linear(a.2), to_linear(b.2), t)),
+            ),
+            ColorSpace::Hsv => {
+                let (h1, s1, v1) = to_hsv(a.0, a.1, a.2);
+                let (h2, s2, v2) = to_hsv(b.0, b.1, b.2);
+
+                // the hue of a gray is taken from the other color
+                let (h1, h2) = if s1 == 0.0 { (h2, h2) } else if s2 == 0.0 { (h1, h1) } else { (h1, h2) };
+                from_hsv(h1 + angle_diff(h1, h2) * t, clamp(lerp(s1, s2, t)), clamp(lerp(v1, v2, t)))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(red: f32, green: f32, blue: f32) -> EffectValue {
+        EffectValue::Color { red: red, green: green, blue: blue, alpha: 255.0 }
+    }
+
+    fn assert_color(value: EffectValue, expected: (f32, f32, f32)) {
+        match value {
+            EffectValue::Color { red, green, blue, .. } => {
+                for &(actual, expected) in &[(red, expected.0), (green, expected.1), (blue, expected.2)] {
+                    assert!((actual - expected).abs() < 0.5, "{:?} != {:?}", (red, green, blue), expected);
+                }
+            },
+            value => panic!("{:?} is not a color", value),
+        }
+    }
+
+    #[test]
+    fn linear_conversions() {
+        assert!((to_linear(0.5) - 0.2140).abs() < 1e-4);
+        assert!((from_linear(0.2140) - 0.5).abs() < 1e-4);
+        for &c in &[0.0, 0.02, 0.04045, 0.3, 1.0] {
+            assert!((from_linear(to_linear(c)) - c).abs() < 1e-5, "{}", c);
+        }
+    }
+
+    #[test]
+    fn hsv_conversions() {
+        assert_eq!(to_hsv(1.0, 0.0, 0.0), (0.0, 1.0, 1.0));
+        assert_eq!(to_hsv(0.0, 0.0, 1.0), (240.0, 1.0, 1.0));
+        assert_eq!(to_hsv(0.5, 0.5, 0.5), (0.0, 0.0, 0.5));
+        assert_eq!(from_hsv(120.0, 1.0, 1.0), (0.0, 1.0, 0.0));
+        assert_eq!(from_hsv(-60.0, 1.0, 1.0), (1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn mix_colors_in_spaces() {
+        let (black, white) = (color(0.0, 0.0, 0.0), color(255.0, 255.0, 255.0));
+        let (red, blue) = (color(255.0, 0.0, 0.0), color(0.0, 0.0, 255.0));
+        let space = |space| ValueType::Color { space: space };
+
+        assert_color(black.mix(&white, 0.5, space(ColorSpace::Srgb)), (127.5, 127.5, 127.5));
+        assert_color(black.mix(&white, 0.5, space(ColorSpace::LinearRgb)), (187.5, 187.5, 187.5));
+
+        // from 0 to 240 degrees through 300, the shorter way
+        assert_color(red.mix(&blue, 0.5, space(ColorSpace::Hsv)), (255.0, 0.0, 255.0));
+        assert_color(white.mix(&red, 0.5, space(ColorSpace::Hsv)), (255.0, 127.5, 127.5));
+    }
+
+    #[test]
+    fn mix_numbers_and_mismatched_values() {
+        use EffectValue::*;
+
+        assert_eq!(Number(350.0).mix(&Number(10.0), 0.5, ValueType::Angle), Number(360.0));
+        assert_eq!(Number(0.0).mix(&Number(10.0), 1.5, ValueType::Scalar), Number(15.0));
+
+        let point = Point { x: 1.0, y: 2.0 };
+        assert_eq!(Number(1.0).mix(&point, 0.4, ValueType::Scalar), Number(1.0));
+        assert_eq!(Number(1.0).mix(&point, 0.5, ValueType::Scalar), point);
+        assert_eq!(point.mix(&color(0.0, 0.0, 0.0), 0.6, ValueType::Point), color(0.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,448 @@
+use std::f32::consts::PI;
+
+// A small arithmetic language evaluated on top of keyframes, such as
+//   value + wiggle(2, 10)
+//   clamp(sin(time * pi) * 100, 0, 50) + effect("<effect id>")
+
+const VARIABLES: &[&str] = &["time", "length", "value", "pi"];
+
+// of parentheses, calls, negations and powers, to keep parse and eval from overflowing the stack
+const MAX_DEPTH: usize = 100;
+
+// the number of arguments taken by functions, except for effect("<effect id>")
+fn arity(name: &str) -> Option<usize> {
+    match name {
+        "sin" | "cos" | "tan" | "abs" | "floor" | "ceil" | "round" | "sqrt" | "noise" => Some(1),
+        "min" | "max" | "wiggle" => Some(2),
+        "clamp" => Some(3),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Str(String),
+    Symbol(char),
+}
+
+fn syntax_error(index: usize, message: &str) -> String {
+    format!("Invalid expression at column {}: {}", index + 1, message)
+}
+
+// tokens with their index in the source
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars = source.chars().collect::<Vec<char>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+
+            let text = chars[start..i].iter().collect::<String>();
+            let number = text.parse::<f32>().map_err(|_| syntax_error(start, &format!("invalid number '{}'", text)))?;
+            tokens.push((start, Token::Number(number)));
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+
+            tokens.push((start, Token::Ident(chars[start..i].iter().collect())));
+        } else if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err(syntax_error(start, "unterminated string"));
+            }
+
+            tokens.push((start, Token::Str(chars[start + 1..i].iter().collect())));
+            i += 1;
+        } else if "+-*/%^(),".contains(c) {
+            tokens.push((start, Token::Symbol(c)));
+            i += 1;
+        } else {
+            return Err(syntax_error(start, &format!("unexpected '{}'", c)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|&(_, ref token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.index += 1;
+        token
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.index).map(|&(column, _)| column).unwrap_or(self.end)
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            None => "the end of expression".to_string(),
+            Some(&Token::Number(number)) => format!("'{}'", number),
+            Some(&Token::Ident(ref name)) => format!("'{}'", name),
+            Some(&Token::Str(ref text)) => format!("\"{}\"", text),
+            Some(&Token::Symbol(symbol)) => format!("'{}'", symbol),
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        syntax_error(self.column(), &format!("{} but found {}", message, self.found()))
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary<F>(&mut self, symbols: &str, operand: F) -> Result<Expr, String>
+        where F: Fn(&mut Parser) -> Result<Expr, String>
+    {
+        // left associative, kept flat so that a long sum is not nested
+        let first = operand(self)?;
+        let mut rest = vec![];
+        loop {
+            let symbol = match self.peek() {
+                Some(&Token::Symbol(symbol)) if symbols.contains(symbol) => symbol,
+                _ => break,
+            };
+            self.index += 1;
+            rest.push((symbol, operand(self)?));
+        }
+
+        if rest.is_empty() {
+            Ok(first)
+        } else {
+            Ok(Expr::Chain(Box::new(first), rest))
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expr, String> {
+        self.binary("+-", Parser::multiplicative)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, String> {
+        self.binary("*/%", Parser::unary)
+    }
+
+    // every nesting, such as parentheses and arguments, goes through here
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(syntax_error(self.column(), &format!("nested more than {} levels", MAX_DEPTH)));
+        }
+
+        self.depth += 1;
+        let expr = self.unary_operand();
+        self.depth -= 1;
+        expr
+    }
+
+    fn unary_operand(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+
+        // right associative
+        let base = self.primary()?;
+        if self.eat('^') {
+            Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?)))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Symbol('(')) => {
+                let expr = self.additive()?;
+                if !self.eat(')') {
+                    return Err(self.error("expected ')'"));
+                }
+
+                Ok(expr)
+            },
+            Some(Token::Ident(name)) => {
+                if self.eat('(') {
+                    self.call(name, column)
+                } else if VARIABLES.contains(&name.as_str()) {
+                    Ok(Expr::Variable(name))
+                } else {
+                    Err(syntax_error(column, &format!("unknown variable '{}', expected one of {}", name, VARIABLES.join(", "))))
+                }
+            },
+            _ => {
+                self.index -= 1;
+                Err(self.error("expected a value"))
+            },
+        }
+    }
+
+    fn call(&mut self, name: String, column: usize) -> Result<Expr, String> {
+        if name == "effect" {
+            let effect_id = match self.next() {
+                Some(Token::Str(effect_id)) => effect_id,
+                _ => {
+                    self.index -= 1;
+                    return Err(self.error("expected the id of an effect in quotes"));
+                },
+            };
+            if !self.eat(')') {
+                return Err(self.error("expected ')'"));
+            }
+
+            return Ok(Expr::Reference(effect_id));
+        }
+
+        let arity = arity(&name).ok_or(syntax_error(column, &format!("unknown function '{}'", name)))?;
+        let mut args = vec![];
+        if !self.eat(')') {
+            loop {
+                args.push(self.additive()?);
+                if self.eat(')') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return Err(self.error("expected ',' or ')'"));
+                }
+            }
+        }
+
+        if args.len() != arity {
+            return Err(syntax_error(column, &format!("{} takes {} argument(s), but {} given", name, arity, args.len())));
+        }
+
+        Ok(Expr::Call(name, args))
+    }
+}
+
+// smooth pseudo-random noise in [-1,1], which is the same for the same x
+pub fn noise(x: f32) -> f32 {
+    fn lattice(i: i64) -> f32 {
+        let mut h = (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        h ^= h >> 32;
+        h = h.wrapping_mul(0xD6E8_FEB8_6659_FD93);
+        h ^= h >> 32;
+
+        (h & 0xFF_FFFF) as f32 / 8_388_607.5 - 1.0
+    }
+
+    let i = x.floor();
+    let f = x - i;
+    let t = f * f * (3.0 - 2.0 * f);
+    let (a, b) = (lattice(i as i64), lattice(i as i64 + 1));
+
+    a + (b - a) * t
+}
+
+fn operate(symbol: char, a: f32, b: f32) -> Result<f32, String> {
+    match symbol {
+        '+' => Ok(a + b),
+        '-' => Ok(a - b),
+        '*' => Ok(a * b),
+        '/' => Ok(a / b),
+        '%' => Ok(a % b),
+        '^' => Ok(a.powf(b)),
+        _ => Err(format!("Unknown operator: {}", symbol)),
+    }
+}
+
+// what variables and references refer to
+pub struct ExprScope<'a> {
+    // in seconds from the start of the component
+    pub time: f32,
+    pub length: f32,
+
+    // the value of keyframes at the time
+    pub value: f32,
+
+    pub referred: &'a Fn(&str) -> Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f32),
+    Variable(String),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+
+    // left associative operators applied in order, such as 1 + 2 - 3
+    Chain(Box<Expr>, Vec<(char, Expr)>),
+    Call(String, Vec<Expr>),
+
+    // the value of another effect, at the time of the component using it
+    Reference(String),
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            index: 0,
+            end: source.chars().count(),
+            depth: 0,
+        };
+
+        let expr = parser.additive()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("expected an operator"));
+        }
+
+        Ok(expr)
+    }
+
+    // ids of the effects referred by the expression
+    pub fn references(&self) -> Vec<String> {
+        use Expr::*;
+
+        match self {
+            &Reference(ref effect_id) => vec![effect_id.clone()],
+            &Neg(ref expr) => expr.references(),
+            &Binary(_, ref lhs, ref rhs) => lhs.references().into_iter().chain(rhs.references()).collect(),
+            &Chain(ref first, ref rest) => first.references().into_iter().chain(rest.iter().flat_map(|&(_, ref operand)| operand.references())).collect(),
+            &Call(_, ref args) => args.iter().flat_map(|arg| arg.references()).collect(),
+            &Number(_) | &Variable(_) => vec![],
+        }
+    }
+
+    pub fn eval(&self, scope: &ExprScope) -> Result<f32, String> {
+        use Expr::*;
+
+        match self {
+            &Number(number) => Ok(number),
+            &Variable(ref name) => match name.as_str() {
+                "time" => Ok(scope.time),
+                "length" => Ok(scope.length),
+                "value" => Ok(scope.value),
+                "pi" => Ok(PI),
+                _ => Err(format!("Unknown variable: {}", name)),
+            },
+            &Neg(ref expr) => Ok(-expr.eval(scope)?),
+            &Binary(symbol, ref lhs, ref rhs) => operate(symbol, lhs.eval(scope)?, rhs.eval(scope)?),
+            &Chain(ref first, ref rest) => rest.iter().fold(first.eval(scope), |lhs, &(symbol, ref operand)| {
+                operate(symbol, lhs?, operand.eval(scope)?)
+            }),
+            &Call(ref name, ref args) => {
+                let args = args.iter().map(|arg| arg.eval(scope)).collect::<Result<Vec<f32>, String>>()?;
+                match name.as_str() {
+                    "sin" => Ok(args[0].sin()),
+                    "cos" => Ok(args[0].cos()),
+                    "tan" => Ok(args[0].tan()),
+                    "abs" => Ok(args[0].abs()),
+                    "floor" => Ok(args[0].floor()),
+                    "ceil" => Ok(args[0].ceil()),
+                    "round" => Ok(args[0].round()),
+                    "sqrt" => Ok(args[0].sqrt()),
+                    "noise" => Ok(noise(args[0])),
+                    "min" => Ok(args[0].min(args[1])),
+                    "max" => Ok(args[0].max(args[1])),
+                    "clamp" => Ok(args[0].max(args[1]).min(args[2])),
+
+                    // value shaken by amp, freq times per second
+                    "wiggle" => Ok(scope.value + args[1] * noise(scope.time * args[0])),
+                    _ => Err(format!("Unknown function: {}", name)),
+                }
+            },
+            &Reference(ref effect_id) => (scope.referred)(effect_id).ok_or(format!("No such effect: {}", effect_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> Result<f32, String> {
+        let referred = |effect_id: &str| if effect_id == "other" { Some(5.0) } else { None };
+        let scope = ExprScope {
+            time: 1.0,
+            length: 4.0,
+            value: 10.0,
+            referred: &referred,
+        };
+
+        Expr::parse(source)?.eval(&scope)
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(eval("1 + 2 * 3 ^ 2"), Ok(19.0));
+        assert_eq!(eval("2 ^ 3 ^ 2"), Ok(512.0));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3.0));
+        assert_eq!(eval("-2 ^ 2"), Ok(-4.0));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9.0));
+    }
+
+    #[test]
+    fn variables_and_functions() {
+        assert_eq!(eval("value * time + length"), Ok(14.0));
+        assert_eq!(eval("clamp(value, 0, 5) + max(1, 2)"), Ok(7.0));
+        assert_eq!(eval("wiggle(0, 3)"), Ok(10.0 + 3.0 * noise(0.0)));
+        assert_eq!(eval("effect(\"other\") * 2"), Ok(10.0));
+        assert!(eval("effect(\"deleted\")").is_err());
+    }
+
+    #[test]
+    fn references() {
+        let expr = Expr::parse("effect(\"a\") + sin(effect(\"b\"))").unwrap();
+        assert_eq!(expr.references(), vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for source in &["", "1 +", "(1", "1 2", "foo", "sin(1, 2)", "bar(1)", "effect(1)", "\"abc", "1 $ 2"] {
+            assert!(Expr::parse(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let depth = 200_000;
+        let parens = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        let negations = format!("{}1", "-".repeat(depth));
+        let powers = format!("1{}", "^1".repeat(depth));
+
+        for source in &[parens, negations, powers] {
+            assert!(Expr::parse(source).unwrap_err().contains("nested"));
+        }
+        assert!(Expr::parse(&format!("{}1{}", "(".repeat(MAX_DEPTH / 2), ")".repeat(MAX_DEPTH / 2))).is_ok());
+    }
+
+    #[test]
+    fn long_chains_are_not_nested() {
+        let depth = 200_000;
+        assert_eq!(eval(&format!("1{}", "+1".repeat(depth))), Ok(depth as f32 + 1.0));
+        assert_eq!(eval(&format!("1{}", "*1".repeat(depth))), Ok(1.0));
+        assert_eq!(eval(&format!("({})", "1+".repeat(MAX_DEPTH + 1) + "1")), Ok(MAX_DEPTH as f32 + 2.0));
+    }
+}
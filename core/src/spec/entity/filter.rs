@@ -0,0 +1,310 @@
+extern crate gdk_pixbuf;
+use std::cmp;
+use std::f32::consts::PI;
+use util::*;
+
+// radius and length are capped for preview to stay responsive
+const MAX_RADIUS: f32 = 100.0;
+const MAX_SAMPLES: usize = 64;
+
+// premultiplied RGBA planes in [0,1], so that transparent pixels do not darken edges
+struct Planes {
+    width: usize,
+    height: usize,
+    planes: Vec<Vec<f32>>,
+}
+
+impl Planes {
+    fn from_pixbuf(pixbuf: &gdk_pixbuf::Pixbuf) -> Planes {
+        let width = pixbuf.get_width() as usize;
+        let height = pixbuf.get_height() as usize;
+        let channels = pixbuf.get_n_channels() as usize;
+        let has_alpha = pixbuf.get_has_alpha();
+        let rowstride = pixbuf.get_rowstride() as usize;
+        let pixels = unsafe { pixbuf.get_pixels() };
+
+        let mut planes = vec![vec![0.0; width * height]; 4];
+        for iy in 0..height {
+            for ix in 0..width {
+                let pos = iy * rowstride + ix * channels;
+                let alpha = if has_alpha { pixels[pos + 3] as f32 / 255.0 } else { 1.0 };
+
+                for c in 0..3 {
+                    planes[c][iy * width + ix] = pixels[pos + c] as f32 / 255.0 * alpha;
+                }
+                planes[3][iy * width + ix] = alpha;
+            }
+        }
+
+        Planes {
+            width: width,
+            height: height,
+            planes: planes,
+        }
+    }
+
+    fn to_pixbuf(&self) -> gdk_pixbuf::Pixbuf {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(
+            gdk_pixbuf::Colorspace::Rgb,
+            true,
+            8,
+            self.width as i32,
+            self.height as i32,
+        );
+        let rowstride = pixbuf.get_rowstride() as usize;
+        let pixels = unsafe { pixbuf.get_pixels() };
+
+        for iy in 0..self.height {
+            for ix in 0..self.width {
+                let pos = iy * rowstride + ix * 4;
+                let alpha = self.planes[3][iy * self.width + ix].max(0.0).min(1.0);
+
+                for c in 0..3 {
+                    let value = if alpha > 0.0 { self.planes[c][iy * self.width + ix] / alpha } else { 0.0 };
+                    pixels[pos + c] = (value.max(0.0).min(1.0) * 255.0).round() as u8;
+                }
+                pixels[pos + 3] = (alpha * 255.0).round() as u8;
+            }
+        }
+
+        pixbuf
+    }
+
+    fn blur(&mut self, radius: f32) {
+        let radius = radius.max(0.0).min(MAX_RADIUS).round() as usize;
+
+        for plane in self.planes.iter_mut() {
+            box_blur(plane, self.width, self.height, radius);
+        }
+    }
+
+    // average of samples along the direction, centered at each pixel
+    fn directional_blur(&self, length: f32, angle: f32) -> Planes {
+        let length = length.max(0.0).min(MAX_RADIUS * 2.0);
+        let samples = cmp::min(length.ceil() as usize, MAX_SAMPLES) + 1;
+        let (sin, cos) = (angle * PI / 180.0).sin_cos();
+        let offsets = (0..samples).map(|i| {
+            let t = if samples > 1 { (i as f32 / (samples - 1) as f32 - 0.5) * length } else { 0.0 };
+            ((t * cos).round() as isize, (t * sin).round() as isize)
+        }).collect::<Vec<_>>();
+
+        let clamp = |v: isize, len: usize| cmp::min(cmp::max(v, 0), len as isize - 1) as usize;
+        let planes = self.planes.iter().map(|plane| {
+            let mut result = vec![0.0; plane.len()];
+
+            for iy in 0..self.height {
+                for ix in 0..self.width {
+                    let sum = offsets.iter().map(|&(dx, dy)| {
+                        plane[clamp(iy as isize + dy, self.height) * self.width + clamp(ix as isize + dx, self.width)]
+                    }).sum::<f32>();
+
+                    result[iy * self.width + ix] = sum / samples as f32;
+                }
+            }
+
+            result
+        }).collect();
+
+        Planes {
+            width: self.width,
+            height: self.height,
+            planes: planes,
+        }
+    }
+
+    fn luma(&self, index: usize) -> f32 {
+        0.2126 * self.planes[0][index] + 0.7152 * self.planes[1][index] + 0.0722 * self.planes[2][index]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    // radius in pixels
+    Blur(f32),
+
+    DirectionalBlur {
+        length: f32,
+        angle: f32,
+    },
+
+    // unsharp mask, amount 0 leaves the image as it is
+    Sharpen {
+        amount: f32,
+        radius: f32,
+    },
+
+    // blurred bright parts, above threshold in [0,1], added onto the image
+    Glow {
+        intensity: f32,
+        radius: f32,
+        threshold: f32,
+    },
+}
+
+impl Filter {
+    fn is_neutral(&self) -> bool {
+        use Filter::*;
+
+        match self {
+            &Blur(radius) => radius < 0.5,
+            &DirectionalBlur { length, .. } => length < 1.0,
+            &Sharpen { amount, radius } => amount == 0.0 || radius < 0.5,
+            &Glow { intensity, .. } => intensity <= 0.0,
+        }
+    }
+
+    // a filtered copy of pixbuf, which is always RGBA
+    pub fn apply_pixbuf(&self, pixbuf: gdk_pixbuf::Pixbuf) -> gdk_pixbuf::Pixbuf {
+        use Filter::*;
+
+        if self.is_neutral() {
+            return pixbuf;
+        }
+
+        let mut planes = Planes::from_pixbuf(&pixbuf);
+        match self {
+            &Blur(radius) => {
+                planes.blur(radius);
+                planes.to_pixbuf()
+            },
+            &DirectionalBlur { length, angle } => planes.directional_blur(length, angle).to_pixbuf(),
+            &Sharpen { amount, radius } => {
+                let mut blurred = Planes::from_pixbuf(&pixbuf);
+                blurred.blur(radius);
+
+                for (plane, blurred) in planes.planes.iter_mut().zip(blurred.planes.iter()) {
+                    for (v, b) in plane.iter_mut().zip(blurred.iter()) {
+                        *v = (*v + amount * (*v - b)).max(0.0);
+                    }
+                }
+
+                // color can not exceed alpha in premultiplied form
+                for index in 0..planes.width * planes.height {
+                    let alpha = planes.planes[3][index].min(1.0);
+                    planes.planes[3][index] = alpha;
+                    for c in 0..3 {
+                        planes.planes[c][index] = planes.planes[c][index].min(alpha);
+                    }
+                }
+
+                planes.to_pixbuf()
+            },
+            &Glow { intensity, radius, threshold } => {
+                let mut bright = Planes::from_pixbuf(&pixbuf);
+                for index in 0..bright.width * bright.height {
+                    let factor = ((planes.luma(index) - threshold) / (1.0 - threshold).max(0.001)).max(0.0).min(1.0);
+                    for plane in bright.planes.iter_mut() {
+                        plane[index] *= factor;
+                    }
+                }
+                bright.blur(radius);
+
+                for (plane, bright) in planes.planes.iter_mut().zip(bright.planes.iter()) {
+                    for (v, b) in plane.iter_mut().zip(bright.iter()) {
+                        *v = (*v + intensity * b).min(1.0);
+                    }
+                }
+
+                planes.to_pixbuf()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a row of RGBA pixels
+    fn row(pixels: &[[u8; 4]]) -> gdk_pixbuf::Pixbuf {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, true, 8, pixels.len() as i32, 1);
+        for (x, p) in pixels.iter().enumerate() {
+            pixbuf.put_pixel(x as i32, 0, p[0], p[1], p[2], p[3]);
+        }
+        pixbuf
+    }
+
+    fn pixels_of(pixbuf: &gdk_pixbuf::Pixbuf) -> Vec<[u8; 4]> {
+        let pixels = unsafe { pixbuf.get_pixels() };
+        (0..pixbuf.get_width() as usize).map(|x| [pixels[x * 4], pixels[x * 4 + 1], pixels[x * 4 + 2], pixels[x * 4 + 3]]).collect()
+    }
+
+    #[test]
+    fn box_blur_keeps_flat_and_total() {
+        let mut flat = vec![0.5; 12];
+        box_blur(&mut flat, 4, 3, 2);
+        assert!(flat.iter().all(|v| (v - 0.5).abs() < 1e-6));
+
+        // an impulse far from the edges spreads symmetrically, keeping its total
+        let mut impulse = vec![0.0; 21];
+        impulse[10] = 1.0;
+        box_blur(&mut impulse, 21, 1, 1);
+        assert!((impulse.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!((0..10).all(|i| (impulse[10 - i] - impulse[10 + i]).abs() < 1e-6));
+        assert!(impulse[10] < 1.0 && impulse[13] > 1e-3 && impulse[14].abs() < 1e-6);
+    }
+
+    #[test]
+    fn blur_radius_is_clamped() {
+        let pixels = (0..5).map(|x| [x * 50, 0, 0, 255]).collect::<Vec<_>>();
+        let blur = |radius| {
+            let mut planes = Planes::from_pixbuf(&row(&pixels));
+            planes.blur(radius);
+            planes.planes
+        };
+
+        assert_eq!(blur(MAX_RADIUS * 10.0), blur(MAX_RADIUS));
+        assert!(blur(MAX_RADIUS) != blur(MAX_RADIUS / 2.0));
+        assert_eq!(blur(-1.0), blur(0.0));
+    }
+
+    #[test]
+    fn blur_does_not_darken_transparent_edges() {
+        let blurred = Filter::Blur(1.0).apply_pixbuf(row(&[[255, 0, 0, 255], [0, 0, 0, 0], [0, 0, 0, 0]]));
+
+        for pixel in pixels_of(&blurred) {
+            assert_eq!(&pixel[0..3], &[255, 0, 0]);
+            assert!(pixel[3] > 0 && pixel[3] < 255);
+        }
+    }
+
+    #[test]
+    fn neutral_filters_keep_pixels() {
+        let pixels = [[10, 20, 30, 255], [200, 100, 0, 128]];
+        for filter in vec![
+            Filter::Blur(0.2),
+            Filter::DirectionalBlur { length: 0.5, angle: 30.0 },
+            Filter::Sharpen { amount: 0.0, radius: 5.0 },
+            Filter::Glow { intensity: 0.0, radius: 5.0, threshold: 0.5 },
+        ] {
+            assert_eq!(pixels_of(&filter.apply_pixbuf(row(&pixels))), pixels.to_vec(), "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn directional_blur_along_the_angle() {
+        let stripes = row(&[[0, 0, 0, 255], [255, 255, 255, 255], [0, 0, 0, 255]]);
+
+        // vertically, the row is the same as its edge
+        let vertical = Filter::DirectionalBlur { length: 2.0, angle: 90.0 }.apply_pixbuf(stripes.copy().unwrap());
+        assert_eq!(pixels_of(&vertical), pixels_of(&stripes));
+
+        let horizontal = Filter::DirectionalBlur { length: 2.0, angle: 0.0 }.apply_pixbuf(stripes);
+        assert_eq!(pixels_of(&horizontal)[1], [85, 85, 85, 255]);
+    }
+
+    #[test]
+    fn sharpen_and_glow() {
+        let flat = [[100, 100, 100, 255]; 3];
+        let sharpened = Filter::Sharpen { amount: 2.0, radius: 1.0 }.apply_pixbuf(row(&flat));
+        assert_eq!(pixels_of(&sharpened), flat.to_vec());
+
+        // darker than the threshold does not glow, while brighter one lights up the neighbors
+        let glow = Filter::Glow { intensity: 1.0, radius: 1.0, threshold: 0.5 };
+        assert_eq!(pixels_of(&glow.apply_pixbuf(row(&flat))), flat.to_vec());
+
+        let glowing = pixels_of(&glow.apply_pixbuf(row(&[[255, 255, 255, 255], [0, 0, 0, 255], [0, 0, 0, 255]])));
+        assert_eq!(glowing[0], [255, 255, 255, 255]);
+        assert!(glowing[1][0] > 0 && glowing[1][0] < 255);
+    }
+}
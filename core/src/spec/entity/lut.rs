@@ -0,0 +1,190 @@
+extern crate gdk_pixbuf;
+use std::fs;
+use util::*;
+
+// the maximum of LUT_3D_SIZE in the .cube specification
+const MAX_LUT_SIZE: usize = 256;
+
+// 3D LUT in the .cube format of Adobe and Resolve
+#[derive(Debug, Clone)]
+pub struct Lut3D {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+
+    // red changes fastest, then green and blue
+    table: Vec<[f32; 3]>,
+}
+
+fn parse_triple(args: &[&str], line: usize) -> Result<[f32; 3], String> {
+    if args.len() != 3 {
+        return Err(format!("line {}: expected 3 values", line));
+    }
+
+    let mut triple = [0.0; 3];
+    for (i, arg) in args.iter().enumerate() {
+        triple[i] = arg.parse().map_err(|_| format!("line {}: invalid number {}", line, arg))?;
+    }
+
+    Ok(triple)
+}
+
+impl Lut3D {
+    pub fn parse(source: &str) -> Result<Lut3D, String> {
+        let mut size = None;
+        let mut domain_min = [0.0, 0.0, 0.0];
+        let mut domain_max = [1.0, 1.0, 1.0];
+        let mut table = vec![];
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+
+            match words.first() {
+                None => (),
+                Some(word) if word.starts_with('#') => (),
+                Some(&"TITLE") => (),
+                Some(&"LUT_3D_SIZE") => {
+                    let n = words.get(1).and_then(|t| t.parse::<usize>().ok()).ok_or(format!("line {}: invalid LUT_3D_SIZE", line_number))?;
+                    if n < 2 || n > MAX_LUT_SIZE {
+                        return Err(format!("line {}: LUT_3D_SIZE should be in [2,{}]", line_number, MAX_LUT_SIZE));
+                    }
+
+                    size = Some(n);
+                },
+                Some(&"LUT_1D_SIZE") => return Err("1D LUT is not supported".to_string()),
+                Some(&"DOMAIN_MIN") => domain_min = parse_triple(&words[1..], line_number)?,
+                Some(&"DOMAIN_MAX") => domain_max = parse_triple(&words[1..], line_number)?,
+
+                // Resolve writes the domain as a range shared by all channels
+                Some(&"LUT_3D_INPUT_RANGE") => {
+                    let range = words[1..].iter().map(|t| t.parse::<f32>()).collect::<Result<Vec<f32>, _>>();
+                    match range.as_ref().map(|range| range.as_slice()) {
+                        Ok(&[min, max]) => {
+                            domain_min = [min; 3];
+                            domain_max = [max; 3];
+                        },
+                        _ => return Err(format!("line {}: invalid LUT_3D_INPUT_RANGE", line_number)),
+                    }
+                },
+
+                // other keywords, such as LUT_1D_INPUT_RANGE, do not affect the 3D table
+                Some(word) if word.starts_with(|c: char| c.is_ascii_alphabetic()) => (),
+                Some(_) => table.push(parse_triple(&words, line_number)?),
+            }
+        }
+
+        let size = size.ok_or("LUT_3D_SIZE is missing".to_string())?;
+        if table.len() != size * size * size {
+            return Err(format!("expected {} entries, but found {}", size * size * size, table.len()));
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err("DOMAIN_MAX should be greater than DOMAIN_MIN".to_string());
+        }
+
+        Ok(Lut3D {
+            size: size,
+            domain_min: domain_min,
+            domain_max: domain_max,
+            table: table,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Lut3D, String> {
+        Lut3D::parse(&fs::read_to_string(path).map_err(|t| format!("{}: {}", path, t))?)
+    }
+
+    fn at(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.table[r + self.size * (g + self.size * b)]
+    }
+
+    // trilinear interpolation of the table, channels in [0,1]
+    pub fn lookup(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let max_index = (self.size - 1) as f32;
+        let mut base = [0; 3];
+        let mut frac = [0.0; 3];
+
+        for c in 0..3 {
+            let v = ((rgb[c] - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c])).max(0.0).min(1.0) * max_index;
+            let i = (v.floor() as usize).min(self.size - 2);
+            base[c] = i;
+            frac[c] = v - i as f32;
+        }
+
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t];
+        let (r, g, b) = (base[0], base[1], base[2]);
+
+        let c00 = lerp(self.at(r, g, b), self.at(r + 1, g, b), frac[0]);
+        let c10 = lerp(self.at(r, g + 1, b), self.at(r + 1, g + 1, b), frac[0]);
+        let c01 = lerp(self.at(r, g, b + 1), self.at(r + 1, g, b + 1), frac[0]);
+        let c11 = lerp(self.at(r, g + 1, b + 1), self.at(r + 1, g + 1, b + 1), frac[0]);
+
+        lerp(lerp(c00, c10, frac[1]), lerp(c01, c11, frac[1]), frac[2])
+    }
+
+    // a graded copy of pixbuf, mixed with the original by intensity in [0,1]
+    pub fn apply_pixbuf(&self, pixbuf: gdk_pixbuf::Pixbuf, intensity: f32) -> gdk_pixbuf::Pixbuf {
+        let intensity = intensity.max(0.0).min(1.0);
+        if intensity == 0.0 {
+            return pixbuf;
+        }
+
+        let pixbuf = writable_copy(&pixbuf, false);
+        let channels = pixbuf.get_n_channels() as usize;
+        let rowstride = pixbuf.get_rowstride() as usize;
+        let pixels = unsafe { pixbuf.get_pixels() };
+
+        for iy in 0..pixbuf.get_height() as usize {
+            for ix in 0..pixbuf.get_width() as usize {
+                let pos = iy * rowstride + ix * channels;
+                let original = [pixels[pos] as f32 / 255.0, pixels[pos + 1] as f32 / 255.0, pixels[pos + 2] as f32 / 255.0];
+                let graded = self.lookup(original);
+
+                for c in 0..3 {
+                    let value = original[c] + (graded[c] - original[c]) * intensity;
+                    pixels[pos + c] = (value.max(0.0).min(1.0) * 255.0).round() as u8;
+                }
+            }
+        }
+
+        pixbuf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: &str = "TITLE \"identity\"\nLUT_3D_SIZE 2\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+
+    #[test]
+    fn parse_identity() {
+        let lut = Lut3D::parse(IDENTITY).unwrap();
+        let rgb = lut.lookup([0.25, 0.5, 0.75]);
+
+        for c in 0..3 {
+            assert!((rgb[c] - [0.25, 0.5, 0.75][c]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn parse_resolve_keywords() {
+        let source = format!("# Resolve\nLUT_1D_INPUT_RANGE 0.0 1.0\nLUT_3D_INPUT_RANGE 0.0 2.0\n{}", IDENTITY);
+        let lut = Lut3D::parse(&source).unwrap();
+
+        assert!((lut.lookup([1.0, 1.0, 1.0])[0] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reject_invalid_size() {
+        assert!(Lut3D::parse("LUT_3D_SIZE 1\n0 0 0\n").is_err());
+        assert!(Lut3D::parse("LUT_3D_SIZE 4000000\n").is_err());
+        assert!(Lut3D::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+    }
+
+    #[test]
+    fn reject_invalid_row() {
+        let source = IDENTITY.replace("1 1 1", "1 1");
+        assert!(Lut3D::parse(&source).is_err());
+    }
+}
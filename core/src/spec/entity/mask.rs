@@ -0,0 +1,320 @@
+extern crate gdk_pixbuf;
+extern crate cairo;
+use std::f64::consts::PI;
+use util::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaskVertex {
+    pub point: (f64, f64),
+
+    // control points of the curves coming in and going out, straight lines if not given
+    #[serde(default)]
+    pub handle_in: Option<(f64, f64)>,
+
+    #[serde(default)]
+    pub handle_out: Option<(f64, f64)>,
+}
+
+impl MaskVertex {
+    fn translate(&mut self, dx: f64, dy: f64) {
+        let shift = |p: (f64, f64)| (p.0 + dx, p.1 + dy);
+
+        self.point = shift(self.point);
+        self.handle_in = self.handle_in.map(shift);
+        self.handle_out = self.handle_out.map(shift);
+    }
+}
+
+// in the coordinate of the component, before its geometry is applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MaskShape {
+    Rectangle {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+    Ellipse {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+    Polygon {
+        vertices: Vec<MaskVertex>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mask {
+    pub shape: MaskShape,
+
+    // radius of the blur on the edge, in pixels
+    #[serde(default)]
+    pub feather: f64,
+
+    #[serde(default)]
+    pub invert: bool,
+}
+
+impl Mask {
+    // set a parameter animated by an effect, "mask.{index}.{name}"
+    // where name is x, y, width, height, feather or point.{index}.{x,y}
+    pub fn set_param(&mut self, name: &str, value: f64) -> Result<(), String> {
+        use MaskShape::*;
+
+        if name == "feather" {
+            self.feather = value;
+            return Ok(());
+        }
+
+        match (&mut self.shape, name) {
+            (&mut Rectangle { ref mut x, .. }, "x") | (&mut Ellipse { ref mut x, .. }, "x") => *x = value,
+            (&mut Rectangle { ref mut y, .. }, "y") | (&mut Ellipse { ref mut y, .. }, "y") => *y = value,
+            (&mut Rectangle { ref mut width, .. }, "width") | (&mut Ellipse { ref mut width, .. }, "width") => *width = value,
+            (&mut Rectangle { ref mut height, .. }, "height") | (&mut Ellipse { ref mut height, .. }, "height") => *height = value,
+            (&mut Polygon { ref mut vertices }, name) if name.starts_with("point.") => {
+                let path = name.split('.').collect::<Vec<&str>>();
+                let index = path.get(1).and_then(|t| t.parse::<usize>().ok()).ok_or(format!("Invalid mask parameter: {}", name))?;
+                let vertex = vertices.get_mut(index).ok_or(format!("No such vertex: {}", index))?;
+
+                match path.get(2) {
+                    Some(&"x") => { let dx = value - vertex.point.0; vertex.translate(dx, 0.0) },
+                    Some(&"y") => { let dy = value - vertex.point.1; vertex.translate(0.0, dy) },
+                    _ => return Err(format!("Invalid mask parameter: {}", name)),
+                }
+            },
+            _ => return Err(format!("Invalid mask parameter: {}", name)),
+        }
+
+        Ok(())
+    }
+
+    // whether set_param accepts the name, regardless of the shape
+    pub fn validate_param_name(name: &str) -> Result<(), String> {
+        let path = name.split('.').collect::<Vec<&str>>();
+
+        match path.as_slice() {
+            &["x"] | &["y"] | &["width"] | &["height"] | &["feather"] => Ok(()),
+            &["point", index, coordinate] if index.parse::<usize>().is_ok() && (coordinate == "x" || coordinate == "y") => Ok(()),
+            _ => Err(format!("Invalid mask parameter: {}", name)),
+        }
+    }
+
+    // whether set_param accepts the name for this shape
+    pub fn validate_param(&self, name: &str) -> Result<(), String> {
+        self.clone().set_param(name, 0.0)
+    }
+
+    fn draw_path(&self, context: &cairo::Context) {
+        use MaskShape::*;
+
+        match &self.shape {
+            &Rectangle { x, y, width, height } => context.rectangle(x, y, width, height),
+            &Ellipse { x, y, width, height } => {
+                if width > 0.0 && height > 0.0 {
+                    context.save();
+                    context.translate(x + width / 2.0, y + height / 2.0);
+                    context.scale(width / 2.0, height / 2.0);
+                    context.arc(0.0, 0.0, 1.0, 0.0, 2.0 * PI);
+                    context.restore();
+                }
+            },
+            &Polygon { ref vertices } => {
+                if let Some(first) = vertices.first() {
+                    context.move_to(first.point.0, first.point.1);
+
+                    for (prev, next) in vertices.iter().zip(vertices.iter().cycle().skip(1)) {
+                        let c1 = prev.handle_out.unwrap_or(prev.point);
+                        let c2 = next.handle_in.unwrap_or(next.point);
+                        context.curve_to(c1.0, c1.1, c2.0, c2.1, next.point.0, next.point.1);
+                    }
+
+                    context.close_path();
+                }
+            },
+        }
+    }
+
+    // opacity of each pixel in [0,1], row by row, on the frame scaled by scale
+    pub fn coverage(&self, width: i32, height: i32, scale: f64) -> Vec<f32> {
+        let mut surface = cairo::ImageSurface::create(cairo::Format::A8, width, height).unwrap();
+        {
+            let context = cairo::Context::new(&surface);
+            context.set_source_rgba(0.0, 0.0, 0.0, 1.0);
+            context.scale(scale, scale);
+            self.draw_path(&context);
+            context.fill();
+        }
+        surface.flush();
+
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().unwrap();
+        let mut values = vec![0.0; (width * height) as usize];
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                values[y * width as usize + x] = data[y * stride + x] as f32 / 255.0;
+            }
+        }
+
+        box_blur(&mut values, width as usize, height as usize, (self.feather * scale).max(0.0).round() as usize);
+
+        if self.invert {
+            for v in values.iter_mut() {
+                *v = 1.0 - *v;
+            }
+        }
+
+        values
+    }
+
+    // a masked copy of pixbuf, where masks are intersected with each other
+    // and scaled by scale, e.g. for proxy frames smaller than the original
+    pub fn mask_pixbuf(pixbuf: gdk_pixbuf::Pixbuf, masks: &[Mask], scale: f64) -> gdk_pixbuf::Pixbuf {
+        if masks.is_empty() {
+            return pixbuf;
+        }
+
+        let pixbuf = writable_copy(&pixbuf, true);
+        let width = pixbuf.get_width();
+        let height = pixbuf.get_height();
+        let rowstride = pixbuf.get_rowstride() as usize;
+        let pixels = unsafe { pixbuf.get_pixels() };
+
+        let coverages = masks.iter().map(|mask| mask.coverage(width, height, scale)).collect::<Vec<_>>();
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let opacity = coverages.iter().fold(1.0, |acc, coverage| acc * coverage[y * width as usize + x]);
+                let pos = y * rowstride + x * 4 + 3;
+                pixels[pos] = (pixels[pos] as f32 * opacity).round() as u8;
+            }
+        }
+
+        pixbuf
+    }
+}
+
+// a layer with track matte is not rendered by itself, but masks the layer below
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TrackMatte {
+    Alpha,
+    AlphaInverted,
+    Luma,
+    LumaInverted,
+}
+
+impl TrackMatte {
+    // opacity given by a pixel of the matte, in [0,1]
+    pub fn opacity(&self, red: u8, green: u8, blue: u8, alpha: u8) -> f32 {
+        use TrackMatte::*;
+
+        let alpha = alpha as f32 / 255.0;
+        let luma = (0.2126 * red as f32 + 0.7152 * green as f32 + 0.0722 * blue as f32) / 255.0 * alpha;
+
+        match self {
+            &Alpha => alpha,
+            &AlphaInverted => 1.0 - alpha,
+            &Luma => luma,
+            &LumaInverted => 1.0 - luma,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rectangle(x: f64, y: f64, width: f64, height: f64) -> Mask {
+        Mask {
+            shape: MaskShape::Rectangle { x: x, y: y, width: width, height: height },
+            feather: 0.0,
+            invert: false,
+        }
+    }
+
+    // rows of coverage written as 0 and 1
+    fn rows(coverage: &[f32], width: usize) -> Vec<String> {
+        coverage.chunks(width).map(|row| row.iter().map(|&v| if v == 1.0 { '1' } else if v == 0.0 { '0' } else { '~' }).collect()).collect()
+    }
+
+    #[test]
+    fn set_params_of_shapes() {
+        let mut mask = rectangle(0.0, 0.0, 10.0, 10.0);
+        mask.set_param("width", 4.0).unwrap();
+        mask.set_param("feather", 2.0).unwrap();
+        assert!(mask.set_param("point.0.x", 1.0).is_err());
+        match mask.shape {
+            MaskShape::Rectangle { width, .. } => assert_eq!(width, 4.0),
+            _ => unreachable!(),
+        }
+        assert_eq!(mask.feather, 2.0);
+
+        // handles move along with the vertex
+        let mut polygon = Mask {
+            shape: MaskShape::Polygon { vertices: vec![MaskVertex { point: (1.0, 1.0), handle_in: Some((0.0, 1.0)), handle_out: None }] },
+            feather: 0.0,
+            invert: false,
+        };
+        polygon.set_param("point.0.x", 3.0).unwrap();
+        match polygon.shape {
+            MaskShape::Polygon { ref vertices } => {
+                assert_eq!(vertices[0].point, (3.0, 1.0));
+                assert_eq!(vertices[0].handle_in, Some((2.0, 1.0)));
+            },
+            _ => unreachable!(),
+        }
+        assert!(polygon.validate_param("point.1.x").is_err());
+        assert!(polygon.validate_param("width").is_err());
+
+        assert!(Mask::validate_param_name("point.2.y").is_ok());
+        for name in &["point.x.y", "point.0.z", "radius", "point.0"] {
+            assert!(Mask::validate_param_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn coverage_of_rectangles() {
+        assert_eq!(rows(&rectangle(1.0, 1.0, 2.0, 1.0).coverage(4, 3, 1.0), 4), vec!["0000", "0110", "0000"]);
+
+        let mut inverted = rectangle(1.0, 1.0, 2.0, 1.0);
+        inverted.invert = true;
+        assert_eq!(rows(&inverted.coverage(4, 3, 1.0), 4), vec!["1111", "1001", "1111"]);
+
+        // the mask is in the coordinate of the original, which is twice of the proxy
+        assert_eq!(rows(&rectangle(0.0, 0.0, 4.0, 2.0).coverage(4, 2, 0.5), 4), vec!["1100", "0000"]);
+    }
+
+    #[test]
+    fn feathered_edges() {
+        let mut mask = rectangle(0.0, 0.0, 4.0, 1.0);
+        mask.feather = 1.0;
+        let coverage = mask.coverage(8, 1, 1.0);
+
+        assert!(coverage[0] > 0.9);
+        assert!(coverage[4] > 0.0 && coverage[4] < coverage[3]);
+        assert!(coverage.windows(2).all(|pair| pair[0] >= pair[1] - 1e-6));
+    }
+
+    #[test]
+    fn masks_are_intersected() {
+        let pixbuf = gdk_pixbuf::Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, false, 8, 3, 1);
+        pixbuf.fill(0xffffffff);
+
+        let masked = Mask::mask_pixbuf(pixbuf, &[rectangle(0.0, 0.0, 2.0, 1.0), rectangle(1.0, 0.0, 2.0, 1.0)], 1.0);
+        let pixels = unsafe { masked.get_pixels() };
+        assert!(masked.get_has_alpha());
+        assert_eq!((pixels[3], pixels[7], pixels[11]), (0, 255, 0));
+    }
+
+    #[test]
+    fn track_matte_opacity() {
+        assert_eq!(TrackMatte::Alpha.opacity(0, 0, 0, 255), 1.0);
+        assert_eq!(TrackMatte::AlphaInverted.opacity(0, 0, 0, 255), 0.0);
+        assert!((TrackMatte::Luma.opacity(255, 255, 255, 255) - 1.0).abs() < 1e-6);
+        assert!((TrackMatte::Luma.opacity(0, 255, 0, 255) - 0.7152).abs() < 1e-6);
+
+        // transparent parts of the matte are dark
+        assert_eq!(TrackMatte::Luma.opacity(255, 255, 255, 0), 0.0);
+        assert_eq!(TrackMatte::LumaInverted.opacity(255, 255, 255, 0), 1.0);
+    }
+}
@@ -1,9 +1,32 @@
 mod effect;
 pub use self::effect::*;
 
+mod effect_value;
+pub use self::effect_value::*;
+
+mod expression;
+pub use self::expression::*;
+
 mod component;
 pub use self::component::*;
 
 mod project;
 pub use self::project::*;
 
+mod mask;
+pub use self::mask::*;
+
+mod color;
+pub use self::color::*;
+
+mod filter;
+pub use self::filter::*;
+
+mod chroma_key;
+pub use self::chroma_key::*;
+
+mod lut;
+pub use self::lut::*;
+
+mod clip_transition;
+pub use self::clip_transition::*;
@@ -1,20 +1,309 @@
 extern crate gdk_pixbuf;
 extern crate gstreamer as gst;
+extern crate serde_json;
 use util::*;
 use spec::*;
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Add,
+    Overlay,
+    Darken,
+    Lighten,
+    Difference,
+}
+
+impl Default for BlendMode {
+    fn default() -> BlendMode {
+        BlendMode::Normal
+    }
+}
+
+impl BlendMode {
+    // blend a channel of the source onto the backdrop, both in [0,1]
+    pub fn blend(&self, backdrop: f32, source: f32) -> f32 {
+        use BlendMode::*;
+
+        match self {
+            &Normal => source,
+            &Multiply => backdrop * source,
+            &Screen => backdrop + source - backdrop * source,
+            &Add => (backdrop + source).min(1.0),
+            &Overlay => {
+                if backdrop <= 0.5 {
+                    2.0 * backdrop * source
+                } else {
+                    1.0 - 2.0 * (1.0 - backdrop) * (1.0 - source)
+                }
+            },
+            &Darken => backdrop.min(source),
+            &Lighten => backdrop.max(source),
+            &Difference => (backdrop - source).abs(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RenderQuality {
+    Nearest,
+    Tiles,
+    Bilinear,
+    Hyper,
+}
+
+impl RenderQuality {
+    pub fn interp_type(&self) -> gdk_pixbuf::InterpType {
+        use RenderQuality::*;
+
+        match self {
+            &Nearest => gdk_pixbuf::InterpType::Nearest,
+            &Tiles => gdk_pixbuf::InterpType::Tiles,
+            &Bilinear => gdk_pixbuf::InterpType::Bilinear,
+            &Hyper => gdk_pixbuf::InterpType::Hyper,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RenderOptions {
+    pub quality: RenderQuality,
+
+    // draft mode composites at this fraction of the project size and upscales the result
+    #[serde(default = "render_scale_default")]
+    pub scale: f64,
+
+    // number of threads rendering frames ahead in AviRenderer, each with its own components
+    #[serde(default = "render_threads_default")]
+    pub threads: usize,
+
+    // decode proxies of components instead of their original media, if generated
+    #[serde(default)]
+    pub proxy: bool,
+}
+
+fn render_scale_default() -> f64 { 1.0 }
+fn render_threads_default() -> usize { 1 }
+
+impl RenderOptions {
+    pub fn preview() -> RenderOptions {
+        RenderOptions {
+            quality: RenderQuality::Nearest,
+            scale: 1.0,
+            threads: 1,
+            proxy: true,
+        }
+    }
+
+    pub fn export() -> RenderOptions {
+        RenderOptions {
+            quality: RenderQuality::Bilinear,
+            scale: 1.0,
+            threads: 1,
+            proxy: false,
+        }
+    }
+
+    pub fn canvas_size(&self, size: (i32, i32)) -> (i32, i32) {
+        let scale = self.scale.max(0.0).min(1.0);
+
+        (cmp::max((size.0 as f64 * scale).round() as i32, 1),
+         cmp::max((size.1 as f64 * scale).round() as i32, 1))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MotionBlur {
+    #[serde(default)]
+    pub enabled: bool,
+
+    // in degrees, 360 keeps the shutter open through the whole frame
+    #[serde(default = "shutter_angle_default")]
+    pub shutter_angle: f32,
+
+    #[serde(default = "motion_blur_samples_default")]
+    pub samples: usize,
+}
+
+fn shutter_angle_default() -> f32 { 180.0 }
+fn motion_blur_samples_default() -> usize { 8 }
+
+const MAX_MOTION_BLUR_SAMPLES: usize = 64;
+
+impl Default for MotionBlur {
+    fn default() -> MotionBlur {
+        MotionBlur {
+            enabled: false,
+            shutter_angle: shutter_angle_default(),
+            samples: motion_blur_samples_default(),
+        }
+    }
+}
+
+impl MotionBlur {
+    // times while the shutter is open, centered at the position
+    pub fn sample_positions(&self, position: gst::ClockTime, fps: i32) -> Vec<gst::ClockTime> {
+        let samples = cmp::min(self.samples, MAX_MOTION_BLUR_SAMPLES);
+        if !self.enabled || samples < 2 || fps <= 0 || self.shutter_angle <= 0.0 {
+            return vec![];
+        }
+
+        let shutter = self.shutter_angle.min(360.0) as f64 / 360.0 / fps as f64 * 1_000_000_000.0;
+        let position = position.nseconds().unwrap_or(0) as f64;
+
+        (0..samples).map(|i| {
+            let t = position - shutter / 2.0 + shutter * i as f64 / (samples - 1) as f64;
+            (t.max(0.0) as u64) * gst::NSECOND
+        }).collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Background {
+    Transparent,
+    Color {
+        red: u8,
+        green: u8,
+        blue: u8,
+    },
+}
+
+impl Default for Background {
+    fn default() -> Background {
+        Background::Color {
+            red: 0,
+            green: 0,
+            blue: 0,
+        }
+    }
+}
+
+impl Background {
+    pub fn transparent() -> Background {
+        Background::Transparent
+    }
+
+    // a transparent background makes frames RGBA
+    pub fn has_alpha(&self) -> bool {
+        self == &Background::Transparent
+    }
+
+    // as the argument of Pixbuf::fill
+    pub fn pixel(&self) -> u32 {
+        match self {
+            &Background::Transparent => 0,
+            &Background::Color { red, green, blue } => (red as u32) << 24 | (green as u32) << 16 | (blue as u32) << 8 | 0xff,
+        }
+    }
+}
+
+// a LUT applied to the whole composite
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutputLut {
+    pub name: String,
+
+    #[serde(default = "lut_intensity_default")]
+    pub intensity: f32,
+}
+
+fn lut_intensity_default() -> f32 { 1.0 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Layer {
     components: Vec<String>,
+
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+
+    // not rendered in preview and export
+    #[serde(default)]
+    pub hidden: bool,
+
+    // rejects any edit through the api
+    #[serde(default)]
+    pub locked: bool,
+
+    // when any layer is solo, only solo layers are rendered and played
+    #[serde(default)]
+    pub solo: bool,
+
+    // no audio is played
+    #[serde(default)]
+    pub muted: bool,
+
+    #[serde(default)]
+    pub track_matte: Option<TrackMatte>,
+
+    // transitions between clips on this layer
+    #[serde(default = "Vec::new")]
+    pub transitions: Vec<ClipTransition>,
 }
 
 impl Layer {
     pub fn new() -> Layer {
         Layer {
             components: vec![],
+            blend_mode: BlendMode::Normal,
+            hidden: false,
+            locked: false,
+            solo: false,
+            muted: false,
+            track_matte: None,
+            transitions: vec![],
         }
     }
 
+    pub fn partial_update(&mut self, value: &serde_json::Map<String, serde_json::Value>) -> Result<(), String> {
+        // flags can be toggled even if the layer is locked
+        let locked = self.locked;
+
+        for (k,v) in value {
+            let as_flag = || v.as_bool().ok_or(format!("{} should be a boolean", k));
+
+            match k.as_str() {
+                "hidden" => self.hidden = as_flag()?,
+                "locked" => self.locked = as_flag()?,
+                "solo" => self.solo = as_flag()?,
+                "muted" => self.muted = as_flag()?,
+                _ if locked => return Err("Layer is locked".to_string()),
+                "blend_mode" => self.blend_mode = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "track_matte" => self.track_matte = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                _ => return Err(format!("Layer has no such property: {}", k)),
+            }
+        }
+
+        Ok(())
+    }
+
+    // both clips should be on the layer, and `to` should start after `from` ends
+    pub fn validate_transition<'a, F>(&self, transition: &ClipTransition, component: F) -> Result<(), String>
+        where F: Fn(&str) -> Option<&'a Component>
+    {
+        for component_id in &[&transition.from, &transition.to] {
+            if !self.components.contains(component_id) {
+                return Err(format!("Component {} is not on the layer", component_id));
+            }
+        }
+
+        let from = component(&transition.from).ok_or(format!("No such component: {}", transition.from))?;
+        let to = component(&transition.to).ok_or(format!("No such component: {}", transition.to))?;
+        if transition.from == transition.to || to.start_time < from.end_time() {
+            return Err("Transition should be between adjacent clips".to_string());
+        }
+
+        transition.easing.validate()
+    }
+
+    pub fn remove(&mut self, component: &str) {
+        self.components.retain(|c| c != component);
+        self.transitions.retain(|t| t.from != component && t.to != component);
+    }
+
     pub fn push(&mut self, component: String) {
         self.components.push(component);
     }
@@ -39,8 +328,50 @@ pub struct Project {
     #[serde(serialize_with = "SerTime::serialize_time")]
     #[serde(deserialize_with = "SerTime::deserialize_time")]
     pub position: gst::ClockTime,
+
+    // used by /screen
+    #[serde(default = "RenderOptions::preview")]
+    pub preview: RenderOptions,
+
+    // used by AviRenderer
+    #[serde(default = "RenderOptions::export")]
+    pub export: RenderOptions,
+
+    #[serde(default)]
+    pub background: Background,
+
+    // where proxies are stored
+    #[serde(default = "cache_dir_default")]
+    pub cache_dir: String,
+
+    // paths of .cube files by name, referred by lut effects and output_lut
+    #[serde(default = "HashMap::new")]
+    pub luts: HashMap<String, String>,
+
+    #[serde(default)]
+    pub output_lut: Option<OutputLut>,
+
+    // frame rate which the shutter of motion blur is based on
+    #[serde(default = "fps_default")]
+    pub fps: i32,
+
+    // overridden by the motion blur of each component
+    #[serde(default)]
+    pub motion_blur: MotionBlur,
+
+    // compositions by name, referred by composition components.
+    // their components are in the same repository, and compositions in them are looked up in this project as well
+    #[serde(default = "HashMap::new")]
+    pub compositions: HashMap<String, Composition>,
+
+    // parsed luts, reloaded by load_luts
+    #[serde(skip)]
+    loaded_luts: HashMap<String, Arc<Lut3D>>,
 }
 
+fn cache_dir_default() -> String { ".madder-cache".to_string() }
+fn fps_default() -> i32 { 30 }
+
 impl Project {
     pub fn new(width: i32, height: i32, length: gst::ClockTime, position: gst::ClockTime) -> Project {
         Project {
@@ -48,9 +379,90 @@ impl Project {
             size: (width, height),
             length: length,
             position: position,
+            preview: RenderOptions::preview(),
+            export: RenderOptions::export(),
+            background: Background::default(),
+            cache_dir: cache_dir_default(),
+            luts: HashMap::new(),
+            output_lut: None,
+            fps: fps_default(),
+            motion_blur: MotionBlur::default(),
+            compositions: HashMap::new(),
+            loaded_luts: HashMap::new(),
         }
     }
 
+    // properties are updated at once, only if the result is valid
+    pub fn partial_update(&mut self, value: &serde_json::Map<String, serde_json::Value>) -> Result<(), String> {
+        let mut updated = self.clone();
+
+        for (k,v) in value {
+            match k.as_str() {
+                "size" => updated.size = SerIntPair::deserialize_pair(v.clone()).map_err(|t| t.to_string())?,
+                "length" => updated.length = serde_json::from_value::<SerTime>(v.clone()).map_err(|t| t.to_string())?.0,
+                "preview" => updated.preview = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "export" => updated.export = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "background" => updated.background = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "cache_dir" => updated.cache_dir = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "luts" => {
+                    updated.luts = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?;
+                    updated.load_lut_files()?;
+                },
+                "fps" => updated.fps = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "motion_blur" => updated.motion_blur = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                "output_lut" => updated.output_lut = serde_json::from_value(v.clone()).map_err(|t| t.to_string())?,
+                _ => return Err(format!("Project has no such property: {}", k)),
+            }
+        }
+
+        updated.validate()?;
+        updated.validate_output_lut()?;
+        *self = updated;
+
+        Ok(())
+    }
+
+    // for properties given by users, either by the API or project files
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fps <= 0 {
+            return Err("fps should be positive".to_string());
+        }
+
+        validate_size(self.size)?;
+        for (name, composition) in &self.compositions {
+            validate_size(composition.size).map_err(|err| format!("Composition {}: {}", name, err))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load_luts(&mut self) -> Result<(), String> {
+        self.load_lut_files()?;
+        self.validate_output_lut()
+    }
+
+    fn load_lut_files(&mut self) -> Result<(), String> {
+        let mut loaded = HashMap::new();
+        for (name, path) in &self.luts {
+            let lut = Lut3D::load(path).map_err(|t| format!("Failed to load LUT {}: {}", name, t))?;
+            loaded.insert(name.clone(), Arc::new(lut));
+        }
+
+        self.loaded_luts = loaded;
+        Ok(())
+    }
+
+    fn validate_output_lut(&self) -> Result<(), String> {
+        match self.output_lut {
+            Some(ref output) if !self.luts.contains_key(&output.name) => Err(format!("No such LUT: {}", output.name)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn lut(&self, name: &str) -> Option<&Lut3D> {
+        self.loaded_luts.get(name).map(|lut| lut.as_ref())
+    }
+
     pub fn insert_layer(&mut self, index: usize) {
         self.layers.insert(index, Layer::new());
     }
@@ -66,6 +478,80 @@ impl Project {
     pub fn get_components_at_layer(&self, layer_index: usize) -> &Vec<String> {
         self.layers[layer_index].list()
     }
+
+    pub fn remove_component(&mut self, component: &str) {
+        for layer in &mut self.layers {
+            layer.remove(component);
+        }
+
+        for composition in self.compositions.values_mut() {
+            composition.remove_component(component);
+        }
+    }
+
+    pub fn find_layer_of(&self, component: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.list().iter().any(|c| c == component))
+    }
+
+    // layers to be rendered, from top to bottom
+    pub fn visible_layers(&self) -> Vec<&Layer> {
+        visible_layers(&self.layers)
+    }
+
+    // layers to be played, from top to bottom
+    pub fn audible_layers(&self) -> Vec<&Layer> {
+        let has_solo = has_solo(&self.layers);
+        self.layers.iter().filter(|layer| !layer.muted && (!has_solo || layer.solo)).collect()
+    }
+}
+
+// frames of zero or negative size can not be created
+fn validate_size(size: (i32, i32)) -> Result<(), String> {
+    if size.0 <= 0 || size.1 <= 0 {
+        return Err(format!("size should be positive, but {}x{} given", size.0, size.1));
+    }
+
+    Ok(())
+}
+
+fn has_solo(layers: &[Layer]) -> bool {
+    layers.iter().any(|layer| layer.solo)
+}
+
+fn visible_layers(layers: &[Layer]) -> Vec<&Layer> {
+    let has_solo = has_solo(layers);
+    layers.iter().filter(|layer| !layer.hidden && (!has_solo || layer.solo)).collect()
+}
+
+// layers rendered as a clip by composition components, with settings such as LUTs and proxies left to the project
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Composition {
+    pub layers: Vec<Layer>,
+
+    #[serde(serialize_with = "SerIntPair::serialize_pair")]
+    #[serde(deserialize_with = "SerIntPair::deserialize_pair")]
+    pub size: (i32, i32),
+
+    #[serde(serialize_with = "SerTime::serialize_time")]
+    #[serde(deserialize_with = "SerTime::deserialize_time")]
+    pub length: gst::ClockTime,
+
+    // composited onto the layers below the composition component
+    #[serde(default = "Background::transparent")]
+    pub background: Background,
+}
+
+impl Composition {
+    pub fn remove_component(&mut self, component: &str) {
+        for layer in &mut self.layers {
+            layer.remove(component);
+        }
+    }
+
+    // layers to be rendered, from top to bottom
+    pub fn visible_layers(&self) -> Vec<&Layer> {
+        visible_layers(&self.layers)
+    }
 }
 
 pub trait HaveProject {
@@ -74,3 +560,34 @@ pub trait HaveProject {
     fn project_mut(&mut self) -> &mut Project;
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> Project {
+        Project::new(640, 480, 10 * gst::SECOND, 0 * gst::SECOND)
+    }
+
+    #[test]
+    fn reject_non_positive_size() {
+        let mut project = project();
+
+        assert!(project.partial_update(json!({ "size": [0, 480] }).as_object().unwrap()).is_err());
+        assert!(project.partial_update(json!({ "size": [640, -1] }).as_object().unwrap()).is_err());
+        assert_eq!(project.size, (640, 480));
+    }
+
+    #[test]
+    fn partial_update_is_atomic() {
+        let mut project = project();
+
+        assert!(project.partial_update(json!({ "fps": 24, "size": [0, 0] }).as_object().unwrap()).is_err());
+        assert!(project.partial_update(json!({ "fps": 24, "output_lut": { "name": "missing" } }).as_object().unwrap()).is_err());
+        assert_eq!(project.fps, 30);
+        assert!(project.output_lut.is_none());
+
+        assert!(project.partial_update(json!({ "fps": 24 }).as_object().unwrap()).is_ok());
+        assert_eq!(project.fps, 24);
+    }
+}
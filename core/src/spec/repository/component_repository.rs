@@ -1,13 +1,23 @@
 extern crate serde_json;
 extern crate serde;
+extern crate gstreamer as gst;
 use spec::*;
 use serde::ser::Serialize;
 
+pub trait ComponentRepository<COMPONENT> : MutRepository<COMPONENT> + RepositoryLoader<COMPONENT> {
+    // components such that start_time <= time <= end_time, ordered by start_time
+    fn active_at(&self, time: gst::ClockTime) -> Vec<&str>;
+
+    // components intersecting [from, to], ordered by start_time
+    fn overlapping(&self, from: gst::ClockTime, to: gst::ClockTime) -> Vec<&str>;
+
+    // should be called after the time of a component is changed through get_mut
+    fn reindex(&mut self, key: &str);
+}
+
 pub trait HaveComponentRepository {
     type COMPONENT : HaveComponent + Serialize + From<serde_json::Value>;
-    type ComponentRepository
-        : MutRepository<Self::COMPONENT>
-        + RepositoryLoader<Self::COMPONENT>;
+    type ComponentRepository : ComponentRepository<Self::COMPONENT>;
 
     fn component_repo(&self) -> &Self::ComponentRepository;
     fn component_repo_mut(&mut self) -> &mut Self::ComponentRepository;
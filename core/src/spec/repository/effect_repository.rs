@@ -1,9 +1,17 @@
 extern crate serde;
+use std::borrow::Cow;
 use spec::*;
 
 pub trait EffectRepository : MutRepository<Effect> + RepositoryLoader<Effect> {
     fn create_intermed(&mut self, &str, EffectPoint);
-    fn value(&self, &str, f32) -> f32;
+    fn value(&self, &str, EffectTime, &Fn(&str) -> EffectTime) -> EffectValue;
+
+    // the effect with the values of the effects referred by its expression bound,
+    // where time_of gives the time to evaluate a referred effect at, and effects referring none are borrowed
+    fn resolve(&self, &str, time_of: &Fn(&str) -> EffectTime) -> Cow<Effect>;
+
+    // referred effects should exist, without any cyclic reference to the effect of the id
+    fn validate_references(&self, Option<&str>, &Effect) -> Result<(), String>;
 }
 
 pub trait HaveEffectRepository {
@@ -0,0 +1,30 @@
+use std::cmp;
+
+// running average over [i - radius, i + radius] of a line, clamped at the edges
+fn blur_line<F: Fn(usize) -> usize>(src: &[f32], dest: &mut [f32], len: usize, index: F, radius: usize) {
+    let at = |i: isize| src[index(cmp::min(cmp::max(i, 0), len as isize - 1) as usize)];
+    let radius = radius as isize;
+    let mut sum = (-radius..radius + 1).map(|i| at(i)).sum::<f32>();
+
+    for i in 0..len as isize {
+        dest[index(i as usize)] = sum / (2 * radius + 1) as f32;
+        sum += at(i + radius + 1) - at(i - radius);
+    }
+}
+
+// approximates gaussian blur by applying box blur three times
+pub fn box_blur(values: &mut [f32], width: usize, height: usize, radius: usize) {
+    if radius == 0 || width == 0 || height == 0 {
+        return;
+    }
+
+    let mut buffer = vec![0.0; values.len()];
+    for _ in 0..3 {
+        for y in 0..height {
+            blur_line(values, &mut buffer, width, |x| y * width + x, radius);
+        }
+        for x in 0..width {
+            blur_line(&buffer, values, height, |y| y * width + x, radius);
+        }
+    }
+}
@@ -1,3 +1,8 @@
 mod serde_impl;
 pub use self::serde_impl::*;
 
+mod blur;
+pub use self::blur::*;
+
+mod pixbuf;
+pub use self::pixbuf::*;
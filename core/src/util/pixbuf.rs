@@ -0,0 +1,11 @@
+extern crate gdk_pixbuf;
+
+// Pixbufs may be shared, e.g. by clones of a component, so that they should be copied before modified in place.
+// with_alpha adds the alpha channel if the pixbuf has none.
+pub fn writable_copy(pixbuf: &gdk_pixbuf::Pixbuf, with_alpha: bool) -> gdk_pixbuf::Pixbuf {
+    if with_alpha && !pixbuf.get_has_alpha() {
+        pixbuf.add_alpha(false, 0, 0, 0)
+    } else {
+        pixbuf.copy()
+    }.unwrap()
+}